@@ -0,0 +1,80 @@
+use super::{BikeProvider, Network, Station};
+use async_trait::async_trait;
+use futures::lock::Mutex as AsyncMutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use surf::Exception;
+const NETWORKS_KEY: &str = "networks";
+
+/// Wraps a provider, keeping the network index for `networks_ttl` and each
+/// network station list for `stations_ttl`.
+pub struct Cached<P> {
+    provider: P,
+    networks: TtlCache<Vec<Network>>,
+    stations: TtlCache<Vec<Station>>,
+}
+
+impl<P: BikeProvider> Cached<P> {
+    pub fn new(provider: P, networks_ttl: Duration, stations_ttl: Duration) -> Self {
+        Cached {
+            provider,
+            networks: TtlCache::new(networks_ttl),
+            stations: TtlCache::new(stations_ttl),
+        }
+    }
+}
+
+#[async_trait]
+impl<P: BikeProvider> BikeProvider for Cached<P> {
+    async fn networks(&self) -> Result<Vec<Network>, Exception> {
+        self.networks
+            .get_or_fetch(NETWORKS_KEY, || self.provider.networks())
+            .await
+    }
+
+    async fn stations(&self, network_href: &str) -> Result<Vec<Station>, Exception> {
+        self.stations
+            .get_or_fetch(network_href, || self.provider.stations(network_href))
+            .await
+    }
+}
+
+type CacheSlot<T> = Arc<AsyncMutex<Option<(Instant, T)>>>;
+
+/// Keeps fetched values for `ttl`. Each key has its own async lock, so
+/// concurrent requests for the same key wait on a single upstream call.
+struct TtlCache<T> {
+    ttl: Duration,
+    slots: Mutex<HashMap<String, CacheSlot<T>>>,
+}
+
+impl<T: Clone> TtlCache<T> {
+    fn new(ttl: Duration) -> Self {
+        TtlCache {
+            ttl,
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn get_or_fetch<F, Fut>(&self, key: &str, fetch: F) -> Result<T, Exception>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, Exception>>,
+    {
+        let slot = {
+            let mut slots = self.slots.lock().unwrap_or_else(|err| err.into_inner());
+            slots.entry(key.to_string()).or_default().clone()
+        };
+        let mut slot = slot.lock().await;
+        if let Some((fetched_at, value)) = slot.as_ref() {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(value.clone());
+            }
+        }
+        let value = fetch().await?;
+        *slot = Some((Instant::now(), value.clone()));
+        Ok(value)
+    }
+}
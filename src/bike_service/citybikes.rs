@@ -0,0 +1,42 @@
+use super::{BikeProvider, Network, Station};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use surf::Exception;
+const CITYBIKES_HOST: &str = "http://api.citybik.es";
+pub const NETWORKS_HREF: &str = "/v2/networks";
+
+/// Client for the [CityBikes](https://api.citybik.es/v2/) aggregator.
+pub struct CityBikes;
+
+#[async_trait]
+impl BikeProvider for CityBikes {
+    async fn networks(&self) -> Result<Vec<Network>, Exception> {
+        #[derive(Deserialize, Serialize)]
+        struct Response {
+            networks: Vec<Network>,
+        }
+        log::debug!("Requesting bike networks");
+        let Response { networks } = surf::get(format!("{}{}", CITYBIKES_HOST, NETWORKS_HREF))
+            .recv_json()
+            .await?;
+        Ok(networks)
+    }
+
+    async fn stations(&self, network_href: &str) -> Result<Vec<Station>, Exception> {
+        #[derive(Deserialize, Serialize)]
+        struct Response {
+            network: Network,
+        }
+        log::debug!("Requesting stations of `{}`", network_href);
+        let Response { network } = surf::get(format!("{}{}", CITYBIKES_HOST, network_href))
+            .recv_json()
+            .await?;
+        let Network { stations, .. } = network;
+        let mut stations = stations.unwrap_or_default();
+        stations.iter_mut().for_each(|station| {
+            station.network_href = Some(network_href.into());
+        });
+
+        Ok(stations)
+    }
+}
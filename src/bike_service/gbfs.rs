@@ -0,0 +1,240 @@
+use super::{BikeProvider, Extra, Network, Station};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use surf::Exception;
+const STATION_INFORMATION: &str = "station_information";
+const STATION_STATUS: &str = "station_status";
+const SYSTEM_INFORMATION: &str = "system_information";
+const PREFERRED_LANGUAGE: &str = "en";
+
+/// Reads stations straight from operators publishing a
+/// [GBFS](https://github.com/NABSA/gbfs) feed. `feeds` maps a network id, as
+/// found on the network href, to its `gbfs.json` discovery url.
+pub struct Gbfs {
+    feeds: HashMap<String, String>,
+}
+
+impl Gbfs {
+    pub fn new(feeds: HashMap<String, String>) -> Self {
+        Gbfs { feeds }
+    }
+
+    pub fn serves(&self, network_href: &str) -> bool {
+        self.discovery_url(network_href).is_some()
+    }
+
+    fn discovery_url(&self, network_href: &str) -> Option<&String> {
+        let network_id = network_href.rsplit('/').next()?;
+        self.feeds.get(network_id)
+    }
+}
+
+#[async_trait]
+impl BikeProvider for Gbfs {
+    /// GBFS has no network index, networks are still listed by CityBikes.
+    async fn networks(&self) -> Result<Vec<Network>, Exception> {
+        Ok(vec![])
+    }
+
+    async fn stations(&self, network_href: &str) -> Result<Vec<Station>, Exception> {
+        let discovery_url = self
+            .discovery_url(network_href)
+            .ok_or_else(|| anyhow!("No GBFS feed for network `{}`", network_href))?;
+        log::debug!("Requesting GBFS stations of `{}`", network_href);
+        let discovery: Discovery = fetch_feed(discovery_url).await?;
+        let feeds = discovery.feeds();
+        let feed_url = |name: &str| {
+            feeds
+                .iter()
+                .find(|feed| feed.name == name)
+                .map(|feed| feed.url.clone())
+                .ok_or_else(|| anyhow!("GBFS feed `{}` missing `{}`", discovery_url, name))
+        };
+        let information_url = feed_url(STATION_INFORMATION)?;
+        let status_url = feed_url(STATION_STATUS)?;
+
+        // Optional here, only the predictions use the timezone
+        let timezone = async {
+            let system_information: Option<SystemInformation> = match feed_url(SYSTEM_INFORMATION) {
+                Ok(url) => fetch_feed(&url).await.ok(),
+                Err(_) => None,
+            };
+            Ok(system_information.map(|information| information.timezone))
+        };
+        let (information, status, timezone): (
+            StationList<StationInformation>,
+            StationList<StationStatus>,
+            Option<String>,
+        ) = futures::try_join!(
+            fetch_feed(&information_url),
+            fetch_feed(&status_url),
+            timezone
+        )?;
+        let mut status: HashMap<String, StationStatus> = status
+            .stations
+            .into_iter()
+            .map(|status| (status.station_id.clone(), status))
+            .collect();
+
+        let stations = information
+            .stations
+            .into_iter()
+            .map(|information| {
+                let status = status.remove(&information.station_id);
+                let timestamp = status
+                    .as_ref()
+                    .and_then(|status| status.last_reported.as_ref())
+                    .and_then(parse_last_reported)
+                    .unwrap_or_else(Utc::now);
+                Station {
+                    free_bikes: status.as_ref().map(|s| s.num_bikes_available),
+                    empty_slots: status.as_ref().and_then(|s| s.num_docks_available),
+                    id: information.station_id,
+                    latitude: information.lat,
+                    longitude: information.lon,
+                    name: information.name.text(),
+                    timestamp,
+                    extra: Some(Extra {
+                        address: information.address,
+                        renting: status.as_ref().and_then(|s| s.is_renting),
+                        returning: status.as_ref().and_then(|s| s.is_returning),
+                        ..Extra::default()
+                    }),
+                    network_href: Some(network_href.into()),
+                    timezone: timezone.clone(),
+                    prediction: None,
+                }
+            })
+            .collect();
+        Ok(stations)
+    }
+}
+
+async fn fetch_feed<T: DeserializeOwned>(url: &str) -> Result<T, Exception> {
+    #[derive(Deserialize)]
+    struct Response<T> {
+        data: T,
+    }
+    let Response { data } = surf::get(url).recv_json().await?;
+    Ok(data)
+}
+
+/// Timestamps are POSIX seconds up to GBFS v2 and RFC3339 strings from v3.
+fn parse_last_reported(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Number(number) => Some(Utc.timestamp(number.as_i64()?, 0)),
+        Value::String(text) => DateTime::parse_from_rfc3339(text)
+            .ok()
+            .map(|date| date.with_timezone(&Utc)),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Discovery {
+    // v3 lists feeds directly
+    Feeds(FeedList),
+    // up to v2 feeds are grouped by language
+    ByLanguage(HashMap<String, FeedList>),
+}
+
+impl Discovery {
+    fn feeds(self) -> Vec<FeedUrl> {
+        match self {
+            Discovery::Feeds(list) => list.feeds,
+            // English if published, otherwise the first language in order
+            Discovery::ByLanguage(mut languages) => languages
+                .remove(PREFERRED_LANGUAGE)
+                .or_else(|| {
+                    let language = languages.keys().min()?.clone();
+                    languages.remove(&language)
+                })
+                .map(|list| list.feeds)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FeedList {
+    feeds: Vec<FeedUrl>,
+}
+
+#[derive(Deserialize)]
+struct FeedUrl {
+    name: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct StationList<T> {
+    stations: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct SystemInformation {
+    timezone: String,
+}
+
+#[derive(Deserialize)]
+struct StationInformation {
+    #[serde(deserialize_with = "string_or_number")]
+    station_id: String,
+    name: Text,
+    lat: f64,
+    lon: f64,
+    address: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StationStatus {
+    #[serde(deserialize_with = "string_or_number")]
+    station_id: String,
+    num_bikes_available: u32,
+    num_docks_available: Option<u32>,
+    last_reported: Option<Value>,
+    #[serde(default, deserialize_with = "super::lenient_flag")]
+    is_renting: Option<bool>,
+    #[serde(default, deserialize_with = "super::lenient_flag")]
+    is_returning: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Text {
+    Plain(String),
+    // v3 localized strings
+    Localized(Vec<LocalizedText>),
+}
+
+#[derive(Deserialize)]
+struct LocalizedText {
+    text: String,
+}
+
+impl Text {
+    fn text(self) -> String {
+        match self {
+            Text::Plain(text) => text,
+            Text::Localized(texts) => texts
+                .into_iter()
+                .next()
+                .map(|localized| localized.text)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Some operators publish numeric station ids.
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(text) => Ok(text),
+        value => Ok(value.to_string()),
+    }
+}
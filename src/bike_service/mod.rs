@@ -0,0 +1,402 @@
+mod cache;
+mod citybikes;
+mod gbfs;
+pub mod station_index;
+use crate::prediction::Prediction;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use cache::Cached;
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use citybikes::CityBikes;
+use gbfs::Gbfs;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use surf::Exception;
+const NETWORKS_TTL: Duration = Duration::from_secs(60 * 60); // 1 hour
+const STATIONS_TTL: Duration = Duration::from_secs(30);
+const WALKING_SPEED: f64 = 1.4; // meters per second, ~5 km/h
+const STALE_AFTER: i64 = 60 * 60; // 1 hour without reporting
+
+/// Source of bike networks and their stations.
+#[async_trait]
+pub trait BikeProvider: Send + Sync {
+    async fn networks(&self) -> Result<Vec<Network>, Exception>;
+    async fn stations(&self, network_href: &str) -> Result<Vec<Station>, Exception>;
+}
+
+/// Built once in `main` and shared by the handlers and the background loops.
+pub type Provider = Arc<dyn BikeProvider>;
+
+/// Cached provider reading the stations of the networks in `gbfs_feeds` from
+/// their operator feed, and everything else from CityBikes.
+pub fn new_provider(gbfs_feeds: HashMap<String, String>) -> Provider {
+    Arc::new(Cached::new(
+        Providers::new(gbfs_feeds),
+        NETWORKS_TTL,
+        STATIONS_TTL,
+    ))
+}
+
+/// Networks are listed by CityBikes, while stations of networks that have a
+/// GBFS feed configured are read from the operator.
+struct Providers {
+    citybikes: CityBikes,
+    gbfs: Gbfs,
+}
+
+impl Providers {
+    fn new(gbfs_feeds: HashMap<String, String>) -> Self {
+        Providers {
+            citybikes: CityBikes,
+            gbfs: Gbfs::new(gbfs_feeds),
+        }
+    }
+}
+
+#[async_trait]
+impl BikeProvider for Providers {
+    async fn networks(&self) -> Result<Vec<Network>, Exception> {
+        self.citybikes.networks().await
+    }
+
+    async fn stations(&self, network_href: &str) -> Result<Vec<Station>, Exception> {
+        if self.gbfs.serves(network_href) {
+            self.gbfs.stations(network_href).await
+        } else {
+            self.citybikes.stations(network_href).await
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Location {
+    latitude: f64,
+    longitude: f64,
+    city: String,
+    country: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Network {
+    href: Option<String>,
+    location: Location,
+    pub name: String,
+    stations: Option<Vec<Station>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Station {
+    pub free_bikes: Option<u32>,
+    pub empty_slots: Option<u32>,
+    pub id: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub name: String,
+    #[serde(default = "Utc::now", deserialize_with = "lenient_timestamp")]
+    pub timestamp: DateTime<Utc>,
+    pub extra: Option<Extra>,
+
+    #[serde(default)]
+    pub network_href: Option<String>,
+    /// IANA name of the network timezone, when its feed tells it.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Filled by `prediction::annotate` from the recorded history.
+    #[serde(skip)]
+    pub prediction: Option<Prediction>,
+}
+
+/// CityBikes station extras. Networks fill them differently, and sometimes
+/// with other types, so anything unexpected is read as missing.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Extra {
+    pub address: Option<String>,
+    pub description: Option<String>,
+    /// `OPEN`, `CLOSED`, `online`... depends on the network
+    #[serde(default, deserialize_with = "lenient_text")]
+    pub status: Option<String>,
+    #[serde(default, deserialize_with = "lenient_amount")]
+    pub ebikes: Option<u32>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub has_ebikes: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_amount")]
+    pub normal_bikes: Option<u32>,
+    /// Total docks
+    #[serde(default, deserialize_with = "lenient_amount")]
+    pub slots: Option<u32>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub renting: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub returning: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_list")]
+    pub payment: Vec<String>,
+}
+
+/// RFC 3339, with or without offset. Unreadable ones are taken as now, so one
+/// station doesn't fail its whole network.
+fn lenient_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DateTime<Utc>, D::Error> {
+    let timestamp = match Value::deserialize(deserializer)? {
+        Value::String(text) => text.parse::<DateTime<Utc>>().ok().or_else(|| {
+            NaiveDateTime::parse_from_str(&text, "%Y-%m-%dT%H:%M:%S%.f")
+                .ok()
+                .map(|timestamp| DateTime::from_utc(timestamp, Utc))
+        }),
+        _ => None,
+    };
+    Ok(timestamp.unwrap_or_else(Utc::now))
+}
+
+fn lenient_text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(text) => Some(text),
+        _ => None,
+    })
+}
+
+fn lenient_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(number) => number.as_u64().map(|number| number as u32),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    })
+}
+
+/// `true`, `1` or `"true"`, as networks differ.
+fn lenient_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Bool(flag) => Some(flag),
+        Value::Number(number) => number.as_u64().map(|number| number != 0),
+        Value::String(text) => match text.to_lowercase().as_str() {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+fn lenient_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Array(values) => values
+            .into_iter()
+            .filter_map(|value| match value {
+                Value::String(text) => Some(text),
+                _ => None,
+            })
+            .collect(),
+        Value::String(text) => vec![text],
+        _ => vec![],
+    })
+}
+
+impl Network {
+    pub async fn stations(&self, provider: &dyn BikeProvider) -> Result<Vec<Station>, Exception> {
+        let Network { href, name, .. } = self;
+        let href = href
+            .as_ref()
+            .ok_or_else(|| anyhow!("Invalid bike network, name:'{}'", name))?;
+        provider.stations(href).await
+    }
+}
+
+impl Station {
+    pub async fn fetch(
+        provider: &dyn BikeProvider,
+        id: &str,
+        network_href: &str,
+    ) -> Result<Self, Exception> {
+        log::debug!("Fetching single station with id `{}`", &id);
+        let stations = provider.stations(network_href).await?;
+        stations
+            .into_iter()
+            .find(|station| station.id == id)
+            .ok_or_else(|| anyhow!("Station not found. Id: {}", id).into())
+    }
+
+    pub fn tz(&self) -> Option<Tz> {
+        self.timezone.as_deref()?.parse().ok()
+    }
+
+    /// Whether the station hasn't reported for a while, its numbers may be off.
+    pub fn is_stale(&self) -> bool {
+        Utc::now().timestamp() - self.timestamp.timestamp() > STALE_AFTER
+    }
+
+    /// Electric bikes available, when the network tells them apart.
+    pub fn ebikes(&self) -> Option<u32> {
+        let extra = self.extra.as_ref()?;
+        if extra.has_ebikes == Some(false) {
+            return None;
+        }
+        extra.ebikes
+    }
+
+    /// Mechanical bikes available, the bikes that aren't electric otherwise.
+    pub fn normal_bikes(&self) -> Option<u32> {
+        let normal_bikes = self.extra.as_ref().and_then(|extra| extra.normal_bikes);
+        normal_bikes.or_else(|| Some(self.free_bikes?.saturating_sub(self.ebikes()?)))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let status = self.extra.as_ref().and_then(|extra| extra.status.as_ref());
+        matches!(
+            status.map(|status| status.to_lowercase()).as_deref(),
+            Some("closed") | Some("offline") | Some("inactive") | Some("maintenance")
+        )
+    }
+
+    pub fn is_renting(&self) -> bool {
+        self.extra.as_ref().and_then(|extra| extra.renting) != Some(false)
+    }
+
+    pub fn is_returning(&self) -> bool {
+        self.extra.as_ref().and_then(|extra| extra.returning) != Some(false)
+    }
+}
+
+pub trait Geo {
+    fn location(&self) -> geoutils::Location;
+
+    /// Walking estimate from `origin` to this place.
+    fn walk_from(&self, origin: &geoutils::Location) -> Walk {
+        Walk::new(origin.haversine_distance_to(&self.location()).meters())
+    }
+}
+
+/// Straight line distance and the time it takes to walk it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Walk {
+    pub meters: f64,
+}
+
+impl Walk {
+    pub fn new(meters: f64) -> Self {
+        Walk { meters }
+    }
+
+    pub fn minutes(&self) -> u32 {
+        (self.meters / WALKING_SPEED / 60.0).ceil().max(1.0) as u32
+    }
+
+    /// `350 m` under a kilometer, `1.2 km` above it.
+    pub fn distance_text(&self) -> String {
+        // Rounded first, so 996 m reads `1.0 km` rather than `1000 m`
+        let meters = (self.meters / 10.0).round() * 10.0;
+        if meters < 1000.0 {
+            format!("{} m", meters as u32)
+        } else {
+            format!("{:.1} km", self.meters / 1000.0)
+        }
+    }
+
+    /// `5 min` under an hour, `1 h 05 min` above it.
+    pub fn time_text(&self) -> String {
+        let minutes = self.minutes();
+        if minutes < 60 {
+            format!("{} min", minutes)
+        } else {
+            format!("{} h {:02} min", minutes / 60, minutes % 60)
+        }
+    }
+}
+
+impl std::fmt::Display for Walk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} · 🚶 {}", self.distance_text(), self.time_text())
+    }
+}
+
+impl Geo for Network {
+    fn location(&self) -> geoutils::Location {
+        let location = &self.location;
+        geoutils::Location::new(location.latitude, location.longitude)
+    }
+}
+
+impl Geo for Station {
+    fn location(&self) -> geoutils::Location {
+        geoutils::Location::new(self.latitude, self.longitude)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_distance_under_a_kilometer_in_meters() {
+        assert_eq!(Walk::new(0.0).distance_text(), "0 m");
+        assert_eq!(Walk::new(4.0).distance_text(), "0 m");
+        assert_eq!(Walk::new(346.0).distance_text(), "350 m");
+        assert_eq!(Walk::new(994.0).distance_text(), "990 m");
+    }
+
+    #[test]
+    fn walk_distance_from_a_kilometer_in_kilometers() {
+        assert_eq!(Walk::new(996.0).distance_text(), "1.0 km");
+        assert_eq!(Walk::new(1000.0).distance_text(), "1.0 km");
+        assert_eq!(Walk::new(1240.0).distance_text(), "1.2 km");
+        assert_eq!(Walk::new(12_760.0).distance_text(), "12.8 km");
+    }
+
+    #[test]
+    fn walk_minutes_round_up() {
+        assert_eq!(Walk::new(0.0).minutes(), 1);
+        assert_eq!(Walk::new(80.0).minutes(), 1);
+        assert_eq!(Walk::new(90.0).minutes(), 2);
+        assert_eq!(Walk::new(400.0).minutes(), 5);
+    }
+
+    #[test]
+    fn walk_time_over_an_hour_in_hours() {
+        assert_eq!(Walk::new(400.0).time_text(), "5 min");
+        assert_eq!(Walk::new(5000.0).time_text(), "1 h 00 min");
+        assert_eq!(Walk::new(5500.0).time_text(), "1 h 06 min");
+    }
+
+    #[test]
+    fn walk_display() {
+        assert_eq!(Walk::new(346.0).to_string(), "350 m · 🚶 5 min");
+    }
+
+    fn station_json(timestamp: &str) -> String {
+        format!(
+            r#"{{"free_bikes": 1, "empty_slots": 2, "id": "a", "latitude": 38.72,
+            "longitude": -9.14, "name": "Rossio", "timestamp": {}, "extra": null}}"#,
+            timestamp
+        )
+    }
+
+    #[test]
+    fn reads_station_timestamps() {
+        let station: Station =
+            serde_json::from_str(&station_json(r#""2020-05-04T12:34:56.789000Z""#)).unwrap();
+        assert_eq!(
+            station.timestamp,
+            Utc.ymd(2020, 5, 4).and_hms_micro(12, 34, 56, 789_000)
+        );
+        let station: Station =
+            serde_json::from_str(&station_json(r#""2020-05-04T12:34:56""#)).unwrap();
+        assert_eq!(station.timestamp, Utc.ymd(2020, 5, 4).and_hms(12, 34, 56));
+    }
+
+    #[test]
+    fn reads_bad_station_timestamps_as_now() {
+        let before = Utc::now();
+        for timestamp in &[r#""yesterday""#, "1588595696", "null"] {
+            let station: Station = serde_json::from_str(&station_json(timestamp)).unwrap();
+            assert!(
+                station.timestamp >= before,
+                "{} read as {}",
+                timestamp,
+                station.timestamp
+            );
+            assert!(!station.is_stale());
+        }
+    }
+}
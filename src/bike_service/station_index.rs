@@ -0,0 +1,195 @@
+use super::{Geo, Station};
+use std::collections::HashMap;
+const CELL_SIZE: f64 = 0.01; // degrees, ~1.1km of latitude
+const METERS_PER_DEGREE: f64 = 111_320.0;
+const FIRST_SEARCH_RADIUS: f64 = 500.0; // meters
+/// Cells around the globe, longitudes wrap at the antimeridian
+const LONGITUDE_CELLS: i64 = (360.0 / CELL_SIZE) as i64;
+
+/// Grid over stations of any number of networks, so lookups don't depend on
+/// which network a station belongs to.
+pub struct StationIndex {
+    stations: Vec<Station>,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl StationIndex {
+    pub fn new(stations: Vec<Station>) -> Self {
+        let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        stations.iter().enumerate().for_each(|(index, station)| {
+            cells
+                .entry(cell(station.latitude, station.longitude))
+                .or_default()
+                .push(index);
+        });
+        StationIndex { stations, cells }
+    }
+
+    /// Stations inside `radius` meters from `location`, closest first.
+    pub fn within(&self, location: &geoutils::Location, radius: f64) -> Vec<&Station> {
+        let latitude = location.latitude();
+        let longitude = location.longitude();
+        let latitude_delta = radius / METERS_PER_DEGREE;
+        let longitude_delta =
+            radius / (METERS_PER_DEGREE * latitude.to_radians().cos().abs().max(0.01));
+        let (min_x, mut min_y) =
+            unwrapped_cell(latitude - latitude_delta, longitude - longitude_delta);
+        let (max_x, mut max_y) =
+            unwrapped_cell(latitude + latitude_delta, longitude + longitude_delta);
+        if max_y - min_y >= LONGITUDE_CELLS {
+            min_y = 0;
+            max_y = LONGITUDE_CELLS - 1;
+        }
+
+        let found: Vec<(f64, &Station)> = (min_x..=max_x)
+            .flat_map(|x| (min_y..=max_y).map(move |y| (x, wrap_longitude_cell(y))))
+            .filter_map(|key| self.cells.get(&key))
+            .flatten()
+            .map(|&index| &self.stations[index])
+            .map(|station| {
+                let distance = location.haversine_distance_to(&station.location()).meters();
+                (distance, station)
+            })
+            .filter(|(distance, _)| *distance <= radius)
+            .collect();
+        by_distance(found)
+    }
+
+    /// Up to `k` stations closest to `location`. The grid is searched up to
+    /// `max_radius` meters, past that every station is compared, so far away
+    /// users still get the nearest ones.
+    pub fn nearest(
+        &self,
+        location: &geoutils::Location,
+        k: usize,
+        max_radius: f64,
+    ) -> Vec<&Station> {
+        let mut radius = FIRST_SEARCH_RADIUS.min(max_radius);
+        let mut found = loop {
+            let found = self.within(location, radius);
+            if found.len() >= k || radius >= max_radius {
+                break found;
+            }
+            radius = (radius * 2.0).min(max_radius);
+        };
+        if found.len() < k {
+            found = by_distance(
+                self.stations
+                    .iter()
+                    .map(|station| {
+                        let distance = location.haversine_distance_to(&station.location()).meters();
+                        (distance, station)
+                    })
+                    .collect(),
+            );
+        }
+        found.truncate(k);
+        found
+    }
+}
+
+fn by_distance(mut found: Vec<(f64, &Station)>) -> Vec<&Station> {
+    found.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    found.into_iter().map(|(_, station)| station).collect()
+}
+
+fn cell(latitude: f64, longitude: f64) -> (i64, i64) {
+    let (x, y) = unwrapped_cell(latitude, longitude);
+    (x, wrap_longitude_cell(y))
+}
+
+/// Cell of a location whose longitude may be past the antimeridian, ranges
+/// of these stay contiguous.
+fn unwrapped_cell(latitude: f64, longitude: f64) -> (i64, i64) {
+    (
+        (latitude / CELL_SIZE).floor() as i64,
+        (longitude / CELL_SIZE).floor() as i64,
+    )
+}
+
+/// Same cell for `180` and `-180` degrees, and for the longitudes past them.
+fn wrap_longitude_cell(y: i64) -> i64 {
+    (y + LONGITUDE_CELLS / 2).rem_euclid(LONGITUDE_CELLS) - LONGITUDE_CELLS / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    fn station(id: &str, latitude: f64, longitude: f64) -> Station {
+        Station {
+            free_bikes: Some(1),
+            empty_slots: Some(1),
+            id: id.to_string(),
+            latitude,
+            longitude,
+            name: id.to_string(),
+            timestamp: Utc::now(),
+            extra: None,
+            network_href: None,
+            timezone: None,
+            prediction: None,
+        }
+    }
+
+    fn ids(stations: Vec<&Station>) -> Vec<&str> {
+        stations
+            .into_iter()
+            .map(|station| station.id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn finds_stations_across_cell_boundaries() {
+        // 0.01 degrees cells, the location and the stations are in different ones
+        let index = StationIndex::new(vec![
+            station("north", 38.7200, -9.1400),
+            station("south", 38.7199, -9.1400),
+            station("far", 38.7500, -9.1400),
+        ]);
+        let location = geoutils::Location::new(38.72001, -9.13999);
+        assert_eq!(ids(index.within(&location, 100.0)), vec!["north", "south"]);
+    }
+
+    #[test]
+    fn finds_stations_across_the_antimeridian() {
+        let index = StationIndex::new(vec![
+            station("east", -16.5, 179.999),
+            station("west", -16.5, -179.999),
+        ]);
+        let location = geoutils::Location::new(-16.5, 179.9995);
+        assert_eq!(ids(index.within(&location, 500.0)), vec!["east", "west"]);
+        let location = geoutils::Location::new(-16.5, -179.9995);
+        assert_eq!(ids(index.within(&location, 500.0)), vec!["west", "east"]);
+    }
+
+    #[test]
+    fn nearest_grows_the_radius_until_enough_stations() {
+        let index = StationIndex::new(vec![
+            station("close", 38.7200, -9.1400),
+            station("further", 38.7400, -9.1400),
+            station("furthest", 38.8000, -9.1400),
+        ]);
+        let location = geoutils::Location::new(38.7200, -9.1400);
+        assert_eq!(
+            ids(index.nearest(&location, 2, 30_000.0)),
+            vec!["close", "further"]
+        );
+    }
+
+    #[test]
+    fn nearest_falls_back_to_stations_past_the_max_radius() {
+        let index = StationIndex::new(vec![
+            station("lisbon", 38.7200, -9.1400),
+            station("porto", 41.1500, -8.6100),
+        ]);
+        // ~100 km from Lisbon
+        let location = geoutils::Location::new(39.6000, -9.0700);
+        assert_eq!(ids(index.nearest(&location, 1, 30_000.0)), vec!["lisbon"]);
+        assert_eq!(
+            ids(index.nearest(&location, 5, 30_000.0)),
+            vec!["lisbon", "porto"]
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use crate::bike_service::{Provider, Station};
+use crate::models::CommuteAlert;
+use crate::prediction;
+use crate::store::{Batch, Condition, Storage};
+use anyhow::Result;
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use futures::future::join_all;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use teloxide::utils::markdown::{bold, escape};
+const COMMUTE_ALERT: &str = "COMMUTE_ALERT";
+const CHAT_COMMUTE_ALERTS: &str = "CHAT_COMMUTE_ALERTS";
+/// Sorted set of alert keys scored by their next run timestamp
+const COMMUTE_ALERT_SCHEDULE: &str = "COMMUTE_ALERT_SCHEDULE";
+/// Longest clock jump looked past, some zones skipped whole days
+const MAX_DST_GAP_MINUTES: i64 = 60 * 24;
+/// Runs later than this, as after downtime, are skipped instead of sent
+const OVERDUE_GRACE_MINUTES: i64 = 10;
+
+impl CommuteAlert {
+    pub fn id(&self) -> String {
+        commute_alert_key(&self.uuid)
+    }
+
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    /// First run after `after`, None when there are no weekdays. A time
+    /// skipped by a DST change runs as soon as the clocks are past it.
+    pub fn next_run_at(&self, after: DateTime<Utc>) -> Option<i64> {
+        let tz = self.tz();
+        let today = after.with_timezone(&tz).date().naive_local();
+        (0..=7)
+            .map(|days| today + chrono::Duration::days(days))
+            .filter(|date| self.weekdays.contains(&date.weekday()))
+            .filter_map(|date| first_valid_instant(tz, date.and_time(self.time)))
+            .map(|run_at| run_at.timestamp())
+            .find(|&run_at| run_at > after.timestamp())
+    }
+
+    /// Weekdays as `Mon, Wed, Fri`
+    pub fn days(&self) -> String {
+        self.weekdays
+            .iter()
+            .map(|weekday| format!("{:?}", weekday))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// `local`, or the first minute after it that exists when it falls in a DST
+/// gap.
+fn first_valid_instant(tz: Tz, local: NaiveDateTime) -> Option<DateTime<Tz>> {
+    (0..=MAX_DST_GAP_MINUTES)
+        .map(|minutes| local + chrono::Duration::minutes(minutes))
+        .find_map(|local| tz.from_local_datetime(&local).earliest())
+}
+
+/// Saves the alert, scheduled to its next run and indexed under its chat.
+pub async fn save_commute_alert(storage: &Storage, alert: &CommuteAlert) -> Result<()> {
+    let next_run_at = alert.next_run_at(Utc::now()).unwrap_or(i64::MAX);
+    let data = serde_json::to_string(alert)?;
+    let key = alert.id();
+    let mut batch = Batch::new();
+    batch
+        .set(&key, &data, None)
+        .zadd(COMMUTE_ALERT_SCHEDULE, next_run_at, &key)
+        .set_add(&chat_commute_alerts_key(alert.chat_id), &key);
+    storage.apply(batch).await?;
+    Ok(())
+}
+
+pub async fn delete_commute_alert(storage: &Storage, alert: &CommuteAlert) -> Result<()> {
+    let key = alert.id();
+    let mut batch = Batch::new();
+    batch
+        .del(&key)
+        .zrem(COMMUTE_ALERT_SCHEDULE, &key)
+        .set_remove(&chat_commute_alerts_key(alert.chat_id), &key);
+    storage.apply(batch).await?;
+    Ok(())
+}
+
+pub async fn find_commute_alert(storage: &Storage, uuid: &str) -> Result<Option<CommuteAlert>> {
+    let alert = match storage.get_optional(&commute_alert_key(uuid)).await? {
+        Some(data) => Some(serde_json::from_str(&data)?),
+        None => None,
+    };
+    Ok(alert)
+}
+
+/// Alerts of a chat, by time of day.
+pub async fn chat_commute_alerts(storage: &Storage, chat_id: i64) -> Result<Vec<CommuteAlert>> {
+    let keys = storage
+        .set_members(&chat_commute_alerts_key(chat_id))
+        .await?;
+    let mut alerts: Vec<CommuteAlert> = load_commute_alerts(storage, &keys).await?;
+    alerts.sort_by_key(|alert| alert.time);
+    Ok(alerts)
+}
+
+async fn load_commute_alerts(storage: &Storage, keys: &[String]) -> Result<Vec<CommuteAlert>> {
+    let alerts = storage
+        .get_multiple(keys)
+        .await?
+        .into_iter()
+        .flatten()
+        .filter_map(|data| serde_json::from_str(&data).ok())
+        .collect();
+    Ok(alerts)
+}
+
+fn commute_alert_key(uuid: &str) -> String {
+    format!("{}:{}", COMMUTE_ALERT, uuid)
+}
+
+fn chat_commute_alerts_key(chat_id: i64) -> String {
+    format!("{}:{}", CHAT_COMMUTE_ALERTS, chat_id)
+}
+
+/// Sends the station status of every alert due and schedules their next run.
+pub async fn check_commute_alerts(
+    storage: &Storage,
+    provider: &Provider,
+    bot: Arc<Bot>,
+) -> Result<()> {
+    let alerts = reschedule_due_alerts(storage, Utc::now()).await?;
+    let mut stations = join_all(alerts.iter().map(|alert| {
+        Station::fetch(
+            provider.as_ref(),
+            &alert.station_info.id,
+            &alert.station_info.network_href,
+        )
+    }))
+    .await;
+    join_all(
+        alerts
+            .iter()
+            .zip(stations.iter_mut())
+            .map(|(alert, station)| prediction::annotate(storage, alert.chat_id, station)),
+    )
+    .await;
+    let send_messages = alerts
+        .iter()
+        .zip(stations)
+        .filter_map(|(alert, station)| match station {
+            Ok(station) => {
+                let title = bold(&escape(alert.lang.catalog().commute_alert_title));
+                let text = format!("{}\n{}", title, station.message(None, alert.lang));
+                Some(
+                    bot.send_message(alert.chat_id, text)
+                        .parse_mode(ParseMode::MarkdownV2)
+                        .disable_web_page_preview(true),
+                )
+            }
+            Err(err) => {
+                log::error!("Error fetching CommuteAlert station {:?}", err);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    let results = join_all(send_messages.iter().map(|send_message| send_message.send())).await;
+    results
+        .iter()
+        .filter_map(|r| r.as_ref().err())
+        .for_each(|err| log::error!("Error sending message {:?}", err));
+    Ok(())
+}
+
+/// Alerts due at `now`, each scheduled to its next run. Alerts deleted
+/// meanwhile, or due more than `OVERDUE_GRACE_MINUTES` ago, are not
+/// returned.
+async fn reschedule_due_alerts(storage: &Storage, now: DateTime<Utc>) -> Result<Vec<CommuteAlert>> {
+    let due_keys = storage
+        .zrange_by_score(COMMUTE_ALERT_SCHEDULE, now.timestamp())
+        .await?;
+    let alerts = load_commute_alerts(storage, &due_keys).await?;
+    log::info!("{} CommuteAlert are due", alerts.len());
+    let mut unschedule = Batch::new();
+    due_keys
+        .iter()
+        .filter(|key| !alerts.iter().any(|alert| &alert.id() == *key))
+        .for_each(|key| {
+            unschedule.zrem(COMMUTE_ALERT_SCHEDULE, key);
+        });
+    storage.apply(unschedule).await?;
+
+    // Scheduled before sending, so a failing station isn't retried every minute
+    let mut active_alerts = vec![];
+    for alert in alerts {
+        let next_run_at = alert.next_run_at(now).unwrap_or(i64::MAX);
+        let data = serde_json::to_string(&alert)?;
+        let key = alert.id();
+        let mut update = Batch::when(Condition::Exists(key.clone()));
+        update
+            .set(&key, &data, None)
+            .zadd(COMMUTE_ALERT_SCHEDULE, next_run_at, &key);
+        if !storage.apply(update).await? {
+            let mut unschedule = Batch::new();
+            unschedule.zrem(COMMUTE_ALERT_SCHEDULE, &key);
+            storage.apply(unschedule).await?;
+            continue;
+        }
+        let grace_start = now - chrono::Duration::minutes(OVERDUE_GRACE_MINUTES);
+        if alert
+            .next_run_at(grace_start)
+            .is_some_and(|run_at| run_at <= now.timestamp())
+        {
+            active_alerts.push(alert);
+        } else {
+            log::info!("Skipping overdue CommuteAlert {}", alert.uuid);
+        }
+    }
+    Ok(active_alerts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Lang;
+    use crate::models::StationReminderInfo;
+    use crate::store::MemoryStore;
+
+    fn alert(weekdays: Vec<Weekday>, time: NaiveTime) -> CommuteAlert {
+        CommuteAlert {
+            uuid: "alert".to_string(),
+            chat_id: 42,
+            station_info: StationReminderInfo {
+                uuid: "station-info".to_string(),
+                name: "Rossio".to_string(),
+                network_href: "/v2/networks/test".to_string(),
+                free_bikes: 1,
+                empty_slots: 9,
+                id: "station".to_string(),
+                ebikes: 0,
+                timestamp: None,
+                walk: None,
+            },
+            weekdays,
+            time,
+            timezone: "Europe/Lisbon".to_string(),
+            lang: Lang::En,
+        }
+    }
+
+    fn every_day() -> Vec<Weekday> {
+        std::iter::successors(Some(Weekday::Mon), |weekday| Some(weekday.succ()))
+            .take(7)
+            .collect()
+    }
+
+    #[test]
+    fn runs_on_the_next_weekday_in_the_alert_timezone() {
+        let alert = alert(
+            vec![Weekday::Mon, Weekday::Wed],
+            NaiveTime::from_hms(8, 0, 0),
+        );
+        // Tuesday
+        let after = Utc.ymd(2021, 6, 1).and_hms(12, 0, 0);
+        // 08:00 summer time
+        let expected = Utc.ymd(2021, 6, 2).and_hms(7, 0, 0);
+        assert_eq!(alert.next_run_at(after), Some(expected.timestamp()));
+    }
+
+    #[test]
+    fn runs_after_a_dst_gap_on_the_same_day() {
+        // Lisbon clocks jump from 01:00 to 02:00 on 2021-03-28
+        let alert = alert(vec![Weekday::Sun], NaiveTime::from_hms(1, 30, 0));
+        let after = Utc.ymd(2021, 3, 27).and_hms(12, 0, 0);
+        // 02:00 summer time
+        let expected = Utc.ymd(2021, 3, 28).and_hms(1, 0, 0);
+        assert_eq!(alert.next_run_at(after), Some(expected.timestamp()));
+    }
+
+    #[test]
+    fn never_runs_without_weekdays() {
+        let alert = alert(vec![], NaiveTime::from_hms(8, 0, 0));
+        assert_eq!(alert.next_run_at(Utc::now()), None);
+    }
+
+    #[tokio::test]
+    async fn reschedules_due_alerts_to_their_next_run() {
+        let storage: Storage = Arc::new(MemoryStore::default());
+        let alert = alert(every_day(), NaiveTime::from_hms(8, 0, 0));
+        save_commute_alert(&storage, &alert).await.unwrap();
+        let due_at = Utc.timestamp(alert.next_run_at(Utc::now()).unwrap(), 0);
+
+        let due = reschedule_due_alerts(&storage, due_at).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert!(reschedule_due_alerts(&storage, due_at)
+            .await
+            .unwrap()
+            .is_empty());
+        let next_due_at = due_at + chrono::Duration::days(1);
+        assert_eq!(
+            reschedule_due_alerts(&storage, next_due_at)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_overdue_alerts_until_their_next_run() {
+        let storage: Storage = Arc::new(MemoryStore::default());
+        let alert = alert(every_day(), NaiveTime::from_hms(8, 0, 0));
+        save_commute_alert(&storage, &alert).await.unwrap();
+        let due_at = Utc.timestamp(alert.next_run_at(Utc::now()).unwrap(), 0);
+
+        let late = due_at + chrono::Duration::minutes(OVERDUE_GRACE_MINUTES - 1);
+        assert_eq!(
+            reschedule_due_alerts(&storage, late).await.unwrap().len(),
+            1
+        );
+
+        let next_due_at = due_at + chrono::Duration::days(1);
+        let too_late = next_due_at + chrono::Duration::hours(2);
+        assert!(reschedule_due_alerts(&storage, too_late)
+            .await
+            .unwrap()
+            .is_empty());
+        // Advanced to the run after, not retried
+        assert!(reschedule_due_alerts(&storage, too_late)
+            .await
+            .unwrap()
+            .is_empty());
+        let following_due_at = next_due_at + chrono::Duration::days(1);
+        assert_eq!(
+            reschedule_due_alerts(&storage, following_due_at)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_deleted_alerts_from_the_schedule() {
+        let storage: Storage = Arc::new(MemoryStore::default());
+        let alert = alert(every_day(), NaiveTime::from_hms(8, 0, 0));
+        save_commute_alert(&storage, &alert).await.unwrap();
+        let due_at = Utc.timestamp(alert.next_run_at(Utc::now()).unwrap(), 0);
+        // Deleted without unscheduling, as when it races with a check
+        storage.del_multiple(&[alert.id()]).await.unwrap();
+
+        assert!(reschedule_due_alerts(&storage, due_at)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(storage
+            .zrange_by_score(COMMUTE_ALERT_SCHEDULE, i64::MAX)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(find_commute_alert(&storage, &alert.uuid)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,10 +1,13 @@
+use std::collections::HashMap;
 use std::env;
 pub struct Config {
     pub telegram_token: String,
     pub poll: bool,
     pub host: String,
     pub port: u16,
-    pub redis_url: String,
+    pub database_url: String,
+    pub redis_pool_size: usize,
+    pub gbfs_feeds: HashMap<String, String>,
 }
 
 impl Config {
@@ -20,13 +23,34 @@ impl Config {
             .unwrap_or_else(|_| "3000".to_string())
             .parse()
             .expect("non interger value");
-        let redis_url: String = env::var("REDIS_URL").expect("Missing REDIS_URL env");
+        // `memory://`, `sqlite://file.db` or a redis url. REDIS_URL is still
+        // read for older deploys
+        let database_url: String = env::var("DATABASE_URL")
+            .or_else(|_| env::var("REDIS_URL"))
+            .expect("Missing DATABASE_URL env");
+        let redis_pool_size: usize = env::var("REDIS_POOL_SIZE")
+            .unwrap_or_else(|_| "4".to_string())
+            .parse()
+            .expect("non interger value");
+        // Format: `network_id=https://.../gbfs.json,other_network_id=...`
+        let gbfs_feeds = env::var("GBFS_FEEDS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|feed| {
+                let mut parts = feed.splitn(2, '=');
+                let network_id = parts.next()?.trim();
+                let url = parts.next()?.trim();
+                Some((network_id.to_string(), url.to_string()))
+            })
+            .collect();
         Config {
             telegram_token: token,
             poll,
             host,
             port,
-            redis_url,
+            database_url,
+            redis_pool_size,
+            gbfs_feeds,
         }
     }
 }
@@ -1,23 +1,192 @@
 use super::models::StationWarn;
-use crate::models::CallbackData;
-use crate::redis_helper;
+use crate::bike_service::Provider;
+use crate::commute_alert;
+use crate::handle_favorites;
+use crate::handle_location;
+use crate::handle_reminders;
+use crate::handle_schedule;
+use crate::handle_settings;
+use crate::i18n::{self, fill, Lang};
+use crate::models::{
+    CallbackData, FavoriteStation, StationMode, StationReminderInfo, WarnKind, WarnSettings,
+};
+use crate::station_low_warn;
+use crate::store::Storage;
 use anyhow::{anyhow, Result};
 use chrono::prelude::*;
 use std::sync::Arc;
 use teloxide::dispatching::DispatcherHandlerCx;
 use teloxide::prelude::*;
 use teloxide::requests::Request;
-use teloxide::types::{CallbackQuery, ChatId, ChatOrInlineMessage};
+use teloxide::types::{
+    CallbackQuery, ChatId, ChatOrInlineMessage, InlineKeyboardButton, InlineKeyboardButtonKind,
+    InlineKeyboardMarkup, Location, Message, ParseMode,
+};
 use uuid::Uuid;
+const CALLBACK_DATA_TTL: usize = 60 * 60 * 6; // 6 hours
 
-pub async fn handle(context: &DispatcherHandlerCx<CallbackQuery>) {
+/// Inline keyboard buttons carry only an uuid, the data behind it is kept on
+/// redis for `CALLBACK_DATA_TTL`.
+pub async fn save_callback_data(
+    storage: &Storage,
+    entries: Vec<(String, CallbackData)>,
+) -> Result<()> {
+    let key_value: Vec<(String, String)> = entries
+        .into_iter()
+        .map(|(uuid, callback_data)| {
+            let callback_data = serde_json::to_string(&callback_data).unwrap_or_default();
+            (uuid, callback_data)
+        })
+        .collect();
+    storage
+        .set_multiple(&key_value, Some(CALLBACK_DATA_TTL))
+        .await?;
+    Ok(())
+}
+
+pub fn new_callback_uuid() -> String {
+    Uuid::new_v4().to_simple().to_string()
+}
+
+pub async fn handle(
+    context: &DispatcherHandlerCx<CallbackQuery>,
+    storage: &Storage,
+    provider: &Provider,
+) {
     let DispatcherHandlerCx { update, bot } = &context;
-    let result = create_station_warn(update, bot.clone()).await;
+    let chat_id = update.message.as_ref().map(|message| message.chat.id);
+    let language_code = update.from.language_code.as_deref();
+    let mut lang = i18n::language(storage, chat_id.unwrap_or_default(), language_code).await;
+    let callback_uuid = update.data.as_deref().unwrap_or_default();
+
+    let result = match load_callback_data(storage, update).await {
+        Ok((CallbackData::StartStationReminder(station_info), message)) => create_station_warn(
+            storage,
+            station_info,
+            WarnKind::Bikes,
+            message,
+            callback_uuid,
+            lang,
+            bot.clone(),
+        )
+        .await
+        .map(|settings| reminder_created(lang.catalog().reminder_created, settings)),
+        Ok((CallbackData::StartSlotReminder(station_info), message)) => create_station_warn(
+            storage,
+            station_info,
+            WarnKind::Slots,
+            message,
+            callback_uuid,
+            lang,
+            bot.clone(),
+        )
+        .await
+        .map(|settings| reminder_created(lang.catalog().slot_reminder_created, settings)),
+        Ok((CallbackData::StartEBikeReminder(station_info), message)) => create_station_warn(
+            storage,
+            station_info,
+            WarnKind::EBikes,
+            message,
+            callback_uuid,
+            lang,
+            bot.clone(),
+        )
+        .await
+        .map(|settings| reminder_created(lang.catalog().ebike_reminder_created, settings)),
+        Ok((CallbackData::SetLanguage(new_lang), message)) => {
+            lang = new_lang;
+            set_language(storage, new_lang, message, bot.clone())
+                .await
+                .map(|_| new_lang.catalog().language_saved.to_string())
+        }
+        Ok((CallbackData::SaveFavorite(favorite), message)) => {
+            handle_favorites::save_favorite(storage, message.chat.id, favorite)
+                .await
+                .map(|saved| {
+                    if saved {
+                        lang.catalog().favorite_saved.to_string()
+                    } else {
+                        lang.catalog().favorites_full.to_string()
+                    }
+                })
+        }
+        Ok((CallbackData::RemoveFavorite(favorite), message)) => {
+            remove_favorite(storage, favorite, message, bot.clone())
+                .await
+                .map(|_| lang.catalog().favorite_removed.to_string())
+        }
+        Ok((
+            CallbackData::StopStationReminder {
+                warn_uuid,
+                refresh_list,
+            },
+            message,
+        )) => stop_station_warn(
+            storage,
+            &warn_uuid,
+            refresh_list,
+            message,
+            callback_uuid,
+            lang,
+            bot.clone(),
+        )
+        .await
+        .map(|_| lang.catalog().reminder_stopped.to_string()),
+        Ok((CallbackData::StartTripReminder { pickup, dropoff }, message)) => create_trip_warns(
+            storage,
+            pickup,
+            dropoff,
+            message,
+            callback_uuid,
+            lang,
+            bot.clone(),
+        )
+        .await
+        .map(|settings| reminder_created(lang.catalog().trip_reminder_created, settings)),
+        Ok((CallbackData::StopTripReminder { warn_uuids }, message)) => {
+            stop_trip_warns(storage, &warn_uuids, message, callback_uuid, bot.clone())
+                .await
+                .map(|_| lang.catalog().reminder_stopped.to_string())
+        }
+        Ok((CallbackData::StationsPage { location, page }, message)) => change_page(
+            storage,
+            provider,
+            &location,
+            page,
+            message,
+            lang,
+            bot.clone(),
+        )
+        .await
+        .map(|_| String::new()),
+        Ok((CallbackData::SetStationMode { location, mode }, message)) => change_station_mode(
+            storage,
+            provider,
+            &location,
+            mode,
+            message,
+            lang,
+            bot.clone(),
+        )
+        .await
+        .map(|_| String::new()),
+        Ok((CallbackData::DeleteCommuteAlert { alert_uuid }, message)) => {
+            delete_commute_alert(storage, &alert_uuid, message, lang, bot.clone())
+                .await
+                .map(|_| lang.catalog().schedule_deleted.to_string())
+        }
+        Ok((CallbackData::UpdateSettings(settings), message)) => {
+            update_settings(storage, settings, message, lang, bot.clone())
+                .await
+                .map(|_| lang.catalog().settings_saved.to_string())
+        }
+        Err(err) => Err(err),
+    };
     let message = match result {
-        Ok(_) => "I will warn you if this station has any changes in the next 30 minutes",
+        Ok(message) => message,
         Err(err) => {
             log::error!("Problem handling callback query. Err: `{:?}`", err);
-            "There was a problem. :("
+            lang.catalog().generic_error.to_string()
         }
     };
 
@@ -29,7 +198,10 @@ pub async fn handle(context: &DispatcherHandlerCx<CallbackQuery>) {
         .await;
 }
 
-async fn create_station_warn(callback_query: &CallbackQuery, bot: Arc<Bot>) -> Result<()> {
+async fn load_callback_data<'a>(
+    storage: &Storage,
+    callback_query: &'a CallbackQuery,
+) -> Result<(CallbackData, &'a Message)> {
     let callback_data = callback_query
         .data
         .as_ref()
@@ -39,23 +211,356 @@ async fn create_station_warn(callback_query: &CallbackQuery, bot: Arc<Bot>) -> R
         .as_ref()
         .ok_or_else(|| anyhow!("Missing message information on callback data"))?;
 
-    let data: String = redis_helper::get(&callback_data).await?;
+    let data: String = storage.get(callback_data).await?;
     let data: CallbackData = serde_json::from_str(&data)?;
-    let CallbackData::StartStationReminder(station_info) = data;
+    Ok((data, message))
+}
 
-    let station_warn = StationWarn {
+fn reminder_created(template: &str, settings: WarnSettings) -> String {
+    fill(
+        template,
+        &[("minutes", &settings.duration_minutes.to_string())],
+    )
+}
+
+/// Returns the settings the new warn will follow.
+async fn create_station_warn(
+    storage: &Storage,
+    station_info: StationReminderInfo,
+    kind: WarnKind,
+    message: &Message,
+    callback_uuid: &str,
+    lang: Lang,
+    bot: Arc<Bot>,
+) -> Result<WarnSettings> {
+    let station_warn = new_station_warn(storage, station_info, kind, message, lang).await?;
+    let stop_callback = station_warn.stop_callback.clone().unwrap_or_default();
+    swap_for_stop_button(message, callback_uuid, stop_callback, lang, bot).await;
+    Ok(station_warn.settings)
+}
+
+/// Watches bikes at the pickup and slots at the dropoff, both stopped by a
+/// single button.
+async fn create_trip_warns(
+    storage: &Storage,
+    pickup: StationReminderInfo,
+    dropoff: StationReminderInfo,
+    message: &Message,
+    callback_uuid: &str,
+    lang: Lang,
+    bot: Arc<Bot>,
+) -> Result<WarnSettings> {
+    let pickup = new_station_warn(storage, pickup, WarnKind::Bikes, message, lang).await?;
+    let dropoff = match new_station_warn(storage, dropoff, WarnKind::Slots, message, lang).await {
+        Ok(dropoff) => dropoff,
+        Err(err) => {
+            // Not left watching half of the trip
+            station_low_warn::delete_station_warns(storage, &[pickup]).await?;
+            return Err(err);
+        }
+    };
+
+    let uuid = new_callback_uuid();
+    let callback_data = CallbackData::StopTripReminder {
+        warn_uuids: vec![pickup.uuid.clone(), dropoff.uuid.clone()],
+    };
+    save_callback_data(storage, vec![(uuid.clone(), callback_data)]).await?;
+    swap_for_stop_button(message, callback_uuid, uuid, lang, bot).await;
+    Ok(pickup.settings)
+}
+
+async fn new_station_warn(
+    storage: &Storage,
+    station_info: StationReminderInfo,
+    kind: WarnKind,
+    message: &Message,
+    lang: Lang,
+) -> Result<StationWarn> {
+    let settings = handle_settings::load_settings(storage, message.chat.id).await?;
+    let mut station_warn = StationWarn {
+        uuid: station_low_warn::station_warn_uuid(message.chat.id, &station_info, kind),
         station_info,
-        uuid: Uuid::new_v4().to_simple().to_string(),
         message_id: message.id,
         updated_at: Utc::now(),
         created_at: Utc::now(),
         chat_id: message.chat.id,
+        lang,
+        kind,
+        settings,
+        stop_callback: None,
+    };
+    station_low_warn::save_station_warn(storage, &mut station_warn).await?;
+    Ok(station_warn)
+}
+
+async fn stop_station_warn(
+    storage: &Storage,
+    warn_uuid: &str,
+    refresh_list: bool,
+    message: &Message,
+    callback_uuid: &str,
+    lang: Lang,
+    bot: Arc<Bot>,
+) -> Result<()> {
+    if let Some(station_warn) = station_low_warn::find_station_warn(storage, warn_uuid).await? {
+        station_low_warn::delete_station_warns(storage, &[station_warn]).await?;
+    }
+    if !refresh_list {
+        remove_tapped_button(message, callback_uuid, bot).await;
+        return Ok(());
+    }
+
+    let (text, keyboard) =
+        handle_reminders::reminders_message(storage, message.chat.id, lang).await?;
+    let edit_message = bot.edit_message_text(
+        ChatOrInlineMessage::Chat {
+            chat_id: ChatId::Id(message.chat.id),
+            message_id: message.id,
+        },
+        text,
+    );
+    let edit_message = match keyboard {
+        Some(keyboard) => edit_message.reply_markup(keyboard),
+        None => edit_message,
+    };
+    edit_message.send().await.log_on_error().await;
+    Ok(())
+}
+
+async fn delete_commute_alert(
+    storage: &Storage,
+    alert_uuid: &str,
+    message: &Message,
+    lang: Lang,
+    bot: Arc<Bot>,
+) -> Result<()> {
+    if let Some(alert) = commute_alert::find_commute_alert(storage, alert_uuid).await? {
+        commute_alert::delete_commute_alert(storage, &alert).await?;
+    }
+    let (text, keyboard) =
+        handle_schedule::schedules_message(storage, message.chat.id, lang).await?;
+    let edit_message = bot.edit_message_text(
+        ChatOrInlineMessage::Chat {
+            chat_id: ChatId::Id(message.chat.id),
+            message_id: message.id,
+        },
+        text,
+    );
+    let edit_message = match keyboard {
+        Some(keyboard) => edit_message.reply_markup(keyboard),
+        None => edit_message,
+    };
+    edit_message.send().await.log_on_error().await;
+    Ok(())
+}
+
+async fn stop_trip_warns(
+    storage: &Storage,
+    warn_uuids: &[String],
+    message: &Message,
+    callback_uuid: &str,
+    bot: Arc<Bot>,
+) -> Result<()> {
+    let mut station_warns = vec![];
+    for warn_uuid in warn_uuids {
+        if let Some(station_warn) = station_low_warn::find_station_warn(storage, warn_uuid).await? {
+            station_warns.push(station_warn);
+        }
+    }
+    station_low_warn::delete_station_warns(storage, &station_warns).await?;
+    remove_tapped_button(message, callback_uuid, bot).await;
+    Ok(())
+}
+
+async fn change_page(
+    storage: &Storage,
+    provider: &Provider,
+    location: &Location,
+    page: usize,
+    message: &Message,
+    lang: Lang,
+    bot: Arc<Bot>,
+) -> Result<()> {
+    let mode = handle_location::load_station_mode(storage, message.chat.id).await?;
+    let stations = handle_location::page_stations(provider, location, page, mode)
+        .await
+        .map_err(|err| anyhow!(err))?;
+    let (text, keyboard) = handle_location::page_message(
+        storage,
+        message.chat.id,
+        location,
+        page,
+        mode,
+        &stations,
+        lang,
+    )
+    .await?;
+    bot.edit_message_text(
+        ChatOrInlineMessage::Chat {
+            chat_id: ChatId::Id(message.chat.id),
+            message_id: message.id,
+        },
+        text,
+    )
+    .parse_mode(ParseMode::MarkdownV2)
+    .disable_web_page_preview(true)
+    .reply_markup(keyboard)
+    .send()
+    .await
+    .log_on_error()
+    .await;
+    Ok(())
+}
+
+/// Remembers the mode for the chat and lists the stations again from the
+/// first page.
+async fn change_station_mode(
+    storage: &Storage,
+    provider: &Provider,
+    location: &Location,
+    mode: StationMode,
+    message: &Message,
+    lang: Lang,
+    bot: Arc<Bot>,
+) -> Result<()> {
+    handle_location::save_station_mode(storage, message.chat.id, mode).await?;
+    change_page(storage, provider, location, 0, message, lang, bot).await
+}
+
+async fn update_settings(
+    storage: &Storage,
+    settings: WarnSettings,
+    message: &Message,
+    lang: Lang,
+    bot: Arc<Bot>,
+) -> Result<()> {
+    handle_settings::save_settings(storage, message.chat.id, settings).await?;
+    let (text, keyboard) = handle_settings::settings_message(storage, settings, lang).await?;
+    bot.edit_message_text(
+        ChatOrInlineMessage::Chat {
+            chat_id: ChatId::Id(message.chat.id),
+            message_id: message.id,
+        },
+        text,
+    )
+    .reply_markup(keyboard)
+    .send()
+    .await
+    .log_on_error()
+    .await;
+    Ok(())
+}
+
+async fn set_language(
+    storage: &Storage,
+    lang: Lang,
+    message: &Message,
+    bot: Arc<Bot>,
+) -> Result<()> {
+    i18n::save_language(storage, message.chat.id, lang).await?;
+    remove_reply_markup(message, bot).await;
+    Ok(())
+}
+
+async fn remove_favorite(
+    storage: &Storage,
+    favorite: FavoriteStation,
+    message: &Message,
+    bot: Arc<Bot>,
+) -> Result<()> {
+    handle_favorites::remove_favorite(storage, message.chat.id, &favorite).await?;
+    remove_reply_markup(message, bot).await;
+    Ok(())
+}
+
+/// Replaces the tapped button with a "Stop" one. The rest of the keyboard, as
+/// the other stations of a page or the page and mode buttons, is kept.
+async fn swap_for_stop_button(
+    message: &Message,
+    callback_uuid: &str,
+    stop_callback: String,
+    lang: Lang,
+    bot: Arc<Bot>,
+) {
+    let keyboard = map_tapped_button(message, callback_uuid, |button| {
+        let text = format!(
+            "{}{}",
+            position_prefix(&button.text),
+            lang.catalog().stop_button
+        );
+        Some(InlineKeyboardButton::callback(text, stop_callback.clone()))
+    });
+    edit_reply_markup(message, keyboard, bot).await;
+}
+
+/// Drops the tapped button, keeping the rest of the keyboard.
+async fn remove_tapped_button(message: &Message, callback_uuid: &str, bot: Arc<Bot>) {
+    let keyboard = map_tapped_button(message, callback_uuid, |_| None);
+    edit_reply_markup(message, keyboard, bot).await;
+}
+
+/// Message keyboard with the tapped button replaced by what `replace`
+/// returns. Rows left empty are dropped.
+fn map_tapped_button(
+    message: &Message,
+    callback_uuid: &str,
+    replace: impl Fn(&InlineKeyboardButton) -> Option<InlineKeyboardButton>,
+) -> Option<InlineKeyboardMarkup> {
+    let rows: Vec<Vec<InlineKeyboardButton>> = message
+        .reply_markup()
+        .map(|keyboard| keyboard.inline_keyboard.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .filter_map(|button| match &button.kind {
+                    InlineKeyboardButtonKind::CallbackData(data) if data == callback_uuid => {
+                        replace(&button)
+                    }
+                    _ => Some(button),
+                })
+                .collect::<Vec<_>>()
+        })
+        .filter(|row| !row.is_empty())
+        .collect();
+    if rows.is_empty() {
+        return None;
+    }
+    Some(
+        rows.into_iter()
+            .fold(InlineKeyboardMarkup::default(), |keyboard, row| {
+                keyboard.append_row(row)
+            }),
+    )
+}
+
+async fn edit_reply_markup(
+    message: &Message,
+    keyboard: Option<InlineKeyboardMarkup>,
+    bot: Arc<Bot>,
+) {
+    let edit_message = bot.edit_message_reply_markup(ChatOrInlineMessage::Chat {
+        chat_id: ChatId::Id(message.chat.id),
+        message_id: message.id,
+    });
+    let edit_message = match keyboard {
+        Some(keyboard) => edit_message.reply_markup(keyboard),
+        None => edit_message,
     };
+    edit_message.send().await.log_on_error().await;
+}
 
-    let key = station_warn.id();
-    let data = serde_json::to_string(&station_warn)?;
-    redis_helper::set_multiple(&[(key, data)], None).await?;
+/// `3. ` of the buttons numbered after their station on a stations page.
+fn position_prefix(text: &str) -> &str {
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 && text[digits..].starts_with(". ") {
+        &text[..digits + 2]
+    } else {
+        ""
+    }
+}
 
+async fn remove_reply_markup(message: &Message, bot: Arc<Bot>) {
     bot.edit_message_reply_markup(ChatOrInlineMessage::Chat {
         chat_id: ChatId::Id(message.chat.id),
         message_id: message.id,
@@ -64,5 +569,4 @@ async fn create_station_warn(callback_query: &CallbackQuery, bot: Arc<Bot>) -> R
     .await
     .log_on_error()
     .await;
-    Ok(())
 }
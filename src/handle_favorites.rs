@@ -0,0 +1,184 @@
+use crate::bike_service::{Provider, Station};
+use crate::handle_callback_query::{new_callback_uuid, save_callback_data};
+use crate::i18n::{fill, Lang};
+use crate::models::{CallbackData, FavoriteStation};
+use crate::prediction;
+use crate::store::{Batch, Condition, Storage};
+use anyhow::Result;
+use futures::future::join_all;
+use std::convert::TryFrom;
+use teloxide::dispatching::DispatcherHandlerCx;
+use teloxide::error_handlers::OnError;
+use teloxide::requests::Request;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Message, ParseMode};
+use teloxide::utils::markdown::escape;
+/// Set of JSON encoded `FavoriteStation`
+const FAVORITE_STATIONS: &str = "FAVORITE_STATIONS";
+const MAX_FAVORITES: usize = 10;
+
+/// `/favorites`, sends the live status of every saved station.
+pub async fn handle(
+    context: &DispatcherHandlerCx<Message>,
+    storage: &Storage,
+    provider: &Provider,
+    lang: Lang,
+) {
+    let catalog = lang.catalog();
+    let favorites = match favorites(storage, context.update.chat_id()).await {
+        Ok(favorites) => favorites,
+        Err(err) => {
+            log::error!("Error loading favorites {:?}", err);
+            context
+                .answer(catalog.generic_error)
+                .send()
+                .await
+                .log_on_error()
+                .await;
+            return;
+        }
+    };
+    if favorites.is_empty() {
+        context
+            .answer(catalog.no_favorites)
+            .send()
+            .await
+            .log_on_error()
+            .await;
+        return;
+    }
+
+    let stations: Vec<_> = favorites
+        .iter()
+        .map(|favorite| Station::fetch(provider.as_ref(), &favorite.id, &favorite.network_href))
+        .collect();
+    let mut stations = join_all(stations).await;
+    prediction::annotate(
+        storage,
+        context.update.chat_id(),
+        stations.iter_mut().flatten(),
+    )
+    .await;
+
+    let (buttons, callback_data): (Vec<_>, Vec<_>) = favorites
+        .iter()
+        .map(|favorite| {
+            let uuid = new_callback_uuid();
+            let button =
+                InlineKeyboardButton::callback(catalog.remove_favorite_button.into(), uuid.clone());
+            (
+                button,
+                (uuid, CallbackData::RemoveFavorite(favorite.clone())),
+            )
+        })
+        .unzip();
+    let buttons_saved = save_callback_data(storage, callback_data).await;
+    if let Err(err) = &buttons_saved {
+        log::error!("Error saving favorites callback data {:?}", err);
+    }
+
+    for ((favorite, station), button) in favorites.iter().zip(stations).zip(buttons) {
+        let text = match station {
+            Ok(station) => station.message(None, lang),
+            Err(err) => {
+                log::error!("Error fetching favorite station {:?}", err);
+                fill(
+                    catalog.favorite_unavailable,
+                    &[("station", &escape(&favorite.name))],
+                )
+            }
+        };
+        let send_message = context
+            .answer(text)
+            .parse_mode(ParseMode::MarkdownV2)
+            .disable_web_page_preview(true)
+            .disable_notification(true);
+        let send_message = if buttons_saved.is_ok() {
+            send_message.reply_markup(InlineKeyboardMarkup::default().append_row(vec![button]))
+        } else {
+            send_message
+        };
+        send_message.send().await.log_on_error().await;
+    }
+}
+
+/// "★ Save" button for each station that can be fetched again later.
+pub async fn save_buttons(
+    storage: &Storage,
+    stations: &[Station],
+    lang: Lang,
+) -> Result<Vec<Option<InlineKeyboardButton>>> {
+    let (buttons, callback_data): (Vec<_>, Vec<_>) = stations
+        .iter()
+        .map(|station| match FavoriteStation::try_from(station) {
+            Ok(favorite) => {
+                let uuid = new_callback_uuid();
+                let button = InlineKeyboardButton::callback(
+                    lang.catalog().save_favorite_button.into(),
+                    uuid.clone(),
+                );
+                (
+                    Some(button),
+                    Some((uuid, CallbackData::SaveFavorite(favorite))),
+                )
+            }
+            Err(_) => (None, None),
+        })
+        .unzip();
+    save_callback_data(storage, callback_data.into_iter().flatten().collect()).await?;
+    Ok(buttons)
+}
+
+/// Saved stations of a chat, by name.
+pub async fn favorites(storage: &Storage, chat_id: i64) -> Result<Vec<FavoriteStation>> {
+    let mut favorites: Vec<FavoriteStation> = storage
+        .set_members(&favorites_key(chat_id))
+        .await?
+        .iter()
+        .filter_map(|member| serde_json::from_str(member).ok())
+        .collect();
+    favorites.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(favorites)
+}
+
+/// Returns false when the chat already has `MAX_FAVORITES` stations.
+pub async fn save_favorite(
+    storage: &Storage,
+    chat_id: i64,
+    favorite: FavoriteStation,
+) -> Result<bool> {
+    let favorites = favorites(storage, chat_id).await?;
+    if favorites.iter().any(|saved| same_station(saved, &favorite)) {
+        return Ok(true);
+    }
+    let key = favorites_key(chat_id);
+    let member = serde_json::to_string(&favorite)?;
+    let mut batch = Batch::when(Condition::SetSmallerThan(key.clone(), MAX_FAVORITES));
+    batch.set_add(&key, &member);
+    storage.apply(batch).await
+}
+
+pub async fn remove_favorite(
+    storage: &Storage,
+    chat_id: i64,
+    favorite: &FavoriteStation,
+) -> Result<()> {
+    let key = favorites_key(chat_id);
+    // Every member of the station, it may have been saved under another name
+    let members: Vec<String> = storage
+        .set_members(&key)
+        .await?
+        .into_iter()
+        .filter(|member| {
+            serde_json::from_str(member).is_ok_and(|saved| same_station(&saved, favorite))
+        })
+        .collect();
+    storage.set_remove(&key, &members).await
+}
+
+fn same_station(a: &FavoriteStation, b: &FavoriteStation) -> bool {
+    a.id == b.id && a.network_href == b.network_href
+}
+
+fn favorites_key(chat_id: i64) -> String {
+    format!("{}:{}", FAVORITE_STATIONS, chat_id)
+}
@@ -0,0 +1,145 @@
+use crate::bike_service::Provider;
+use crate::i18n::{fill, Lang};
+use crate::search::{last_network, search};
+use crate::station_history::{hourly_averages, station_history, HourlyAverage, HISTORY_DAYS};
+use crate::store::Storage;
+use teloxide::dispatching::DispatcherHandlerCx;
+use teloxide::error_handlers::OnError;
+use teloxide::requests::Request;
+use teloxide::types::{Message, ParseMode};
+use teloxide::utils::markdown::escape;
+
+/// `/history <station>`, hourly averages of the best match of `<station>` on
+/// the user last network.
+pub async fn handle(
+    context: &DispatcherHandlerCx<Message>,
+    storage: &Storage,
+    provider: &Provider,
+    lang: Lang,
+) {
+    let catalog = lang.catalog();
+    let message = &context.update;
+    let query = message
+        .text()
+        .unwrap_or_default()
+        .split_whitespace()
+        .skip(1)
+        .collect::<Vec<_>>()
+        .join(" ");
+    if query.is_empty() {
+        context
+            .answer(catalog.history_usage)
+            .send()
+            .await
+            .log_on_error()
+            .await;
+        return;
+    }
+    let network_href = match message.from() {
+        Some(user) => last_network(storage, user.id).await.unwrap_or_else(|err| {
+            log::error!("Error loading last network {:?}", err);
+            None
+        }),
+        None => None,
+    };
+    let network_href = match network_href {
+        Some(network_href) => network_href,
+        None => {
+            crate::handle_start(context, lang).await;
+            return;
+        }
+    };
+
+    let stations = match provider.stations(&network_href).await {
+        Ok(stations) => stations,
+        Err(err) => {
+            log::error!("Error fetching stations {:?}", err);
+            context
+                .answer(catalog.list_stations_error)
+                .send()
+                .await
+                .log_on_error()
+                .await;
+            return;
+        }
+    };
+    let station = match search(&stations, &query, None, 1).into_iter().next() {
+        Some(station) => station,
+        None => {
+            context
+                .answer(fill(catalog.search_no_results, &[("query", &query)]))
+                .send()
+                .await
+                .log_on_error()
+                .await;
+            return;
+        }
+    };
+
+    let samples = match station_history(storage, station).await {
+        Ok(samples) => samples,
+        Err(err) => {
+            log::error!("Error loading station history {:?}", err);
+            context
+                .answer(catalog.generic_error)
+                .send()
+                .await
+                .log_on_error()
+                .await;
+            return;
+        }
+    };
+    let averages = hourly_averages(&samples);
+    if averages.is_empty() {
+        context
+            .answer(fill(catalog.history_empty, &[("station", &station.name)]))
+            .send()
+            .await
+            .log_on_error()
+            .await;
+        return;
+    }
+    context
+        .answer(history_message(&station.name, &averages, lang))
+        .parse_mode(ParseMode::MarkdownV2)
+        .send()
+        .await
+        .log_on_error()
+        .await;
+}
+
+/// MarkdownV2 title with a monospaced table, one row per hour.
+fn history_message(station_name: &str, averages: &[HourlyAverage], lang: Lang) -> String {
+    let catalog = lang.catalog();
+    let title = fill(
+        catalog.history_title,
+        &[
+            ("station", station_name),
+            ("days", &HISTORY_DAYS.to_string()),
+        ],
+    );
+    let hour_width = catalog.history_hour.chars().count().max(3);
+    let bikes_width = catalog.bikes_label.chars().count().max(5);
+    let slots_width = catalog.free_slots_label.chars().count().max(5);
+    let mut rows = vec![format!(
+        "{:<hour_width$}  {:>bikes_width$}  {:>slots_width$}",
+        catalog.history_hour,
+        catalog.bikes_label,
+        catalog.free_slots_label,
+        hour_width = hour_width,
+        bikes_width = bikes_width,
+        slots_width = slots_width
+    )];
+    rows.extend(averages.iter().map(|average| {
+        format!(
+            "{:<hour_width$}  {:>bikes_width$.1}  {:>slots_width$.1}",
+            format!("{:02}h", average.hour),
+            average.free_bikes,
+            average.empty_slots,
+            hour_width = hour_width,
+            bikes_width = bikes_width,
+            slots_width = slots_width
+        )
+    }));
+    format!("{}\n```\n{}\n```", escape(&title), rows.join("\n"))
+}
@@ -0,0 +1,118 @@
+use crate::bike_service::{Geo, Provider, Station};
+use crate::handle_location::near_network_stations;
+use crate::i18n::{self, Lang};
+use crate::search::{self, last_network, save_last_network};
+use crate::store::Storage;
+use surf::Exception;
+use teloxide::dispatching::DispatcherHandlerCx;
+use teloxide::error_handlers::OnError;
+use teloxide::requests::Request;
+use teloxide::types::{
+    InlineQuery, InlineQueryResult, InlineQueryResultArticle, InputMessageContent, ParseMode,
+};
+const INLINE_RESULTS_LIMIT: usize = 20;
+const INLINE_CACHE_TIME: i32 = 30; // seconds, same as the stations cache
+
+/// `@bot <text>`, station search from any chat. Ranked by distance when the
+/// client shares its location, otherwise searched on the user last network.
+pub async fn handle(
+    context: &DispatcherHandlerCx<InlineQuery>,
+    storage: &Storage,
+    provider: &Provider,
+) {
+    let DispatcherHandlerCx { update, bot } = &context;
+    let user_id = update.from.id;
+    let language_code = update.from.language_code.as_deref();
+    let lang = i18n::language(storage, i64::from(user_id), language_code).await;
+
+    let answer = match inline_stations(storage, provider, update).await {
+        Ok(Some(stations)) => {
+            let user_location = update
+                .location
+                .as_ref()
+                .map(|location| geoutils::Location::new(location.latitude, location.longitude));
+            let found = search::search(
+                &stations,
+                &update.query,
+                user_location.as_ref(),
+                INLINE_RESULTS_LIMIT,
+            );
+            let results: Vec<InlineQueryResult> = found
+                .into_iter()
+                .enumerate()
+                .map(|(index, station)| inline_result(index, station, user_location.as_ref(), lang))
+                .collect();
+            bot.answer_inline_query(&update.id, results)
+        }
+        Ok(None) => bot
+            .answer_inline_query(&update.id, vec![])
+            .switch_pm_text(lang.catalog().inline_send_location)
+            .switch_pm_parameter("inline"),
+        Err(err) => {
+            log::error!("Error fetching inline stations {:?}", err);
+            bot.answer_inline_query(&update.id, vec![])
+        }
+    };
+    answer
+        .cache_time(INLINE_CACHE_TIME)
+        .is_personal(true)
+        .send()
+        .await
+        .log_on_error()
+        .await;
+}
+
+/// None when there is no location and no network known for the user yet.
+async fn inline_stations(
+    storage: &Storage,
+    provider: &Provider,
+    update: &InlineQuery,
+) -> Result<Option<Vec<Station>>, Exception> {
+    if let Some(location) = &update.location {
+        let stations = near_network_stations(provider, location).await?;
+        if let Some(network_href) = stations.first().and_then(|s| s.network_href.as_ref()) {
+            save_last_network(storage, update.from.id, network_href).await?;
+        }
+        return Ok(Some(stations));
+    }
+    match last_network(storage, update.from.id).await? {
+        Some(network_href) => Ok(Some(provider.stations(&network_href).await?)),
+        None => Ok(None),
+    }
+}
+
+fn inline_result(
+    index: usize,
+    station: &Station,
+    user_location: Option<&geoutils::Location>,
+    lang: Lang,
+) -> InlineQueryResult {
+    let catalog = lang.catalog();
+    let amount = |value: Option<u32>| value.map_or(String::from("??"), |num| num.to_string());
+    let mut description = format!(
+        "{}: {} · {}: {}",
+        catalog.bikes_label,
+        amount(station.free_bikes),
+        catalog.free_slots_label,
+        amount(station.empty_slots)
+    );
+    if let Some(user_location) = user_location {
+        description = format!("{}\n{}", station.walk_from(user_location), description);
+    }
+    InlineQueryResult::Article(InlineQueryResultArticle {
+        id: index.to_string(),
+        title: station.name.clone(),
+        input_message_content: InputMessageContent::Text {
+            message_text: station.message(user_location, lang),
+            parse_mode: Some(ParseMode::MarkdownV2),
+            disable_web_page_preview: Some(true),
+        },
+        reply_markup: None,
+        url: None,
+        hide_url: None,
+        description: Some(description),
+        thumb_url: None,
+        thumb_width: None,
+        thumb_height: None,
+    })
+}
@@ -0,0 +1,206 @@
+use crate::bike_service::Provider;
+use crate::handle_location::{find_near_stations, load_station_mode};
+use crate::i18n::{self, Lang};
+use crate::models::LiveLocation;
+use crate::prediction;
+use crate::store::{Batch, Condition, Storage};
+use anyhow::{anyhow, Result};
+use chrono::prelude::*;
+use teloxide::dispatching::DispatcherHandlerCx;
+use teloxide::error_handlers::OnError;
+use teloxide::requests::Request;
+use teloxide::types::{ChatId, ChatOrInlineMessage, Location, Message, ParseMode};
+use teloxide::utils::markdown::escape;
+const LIVE_LOCATION: &str = "LIVE_LOCATION";
+/// Claimed by the first location update, which sends the results message
+const LIVE_LOCATION_RESULTS: &str = "LIVE_LOCATION_RESULTS";
+const LIVE_STATIONS_TAKE: usize = 3;
+const LIVE_REFRESH_SECONDS: i64 = 30; // same as the stations cache
+const MAX_LIVE_PERIOD: i64 = 60 * 60 * 24; // 24 hours
+
+/// `edited_message` updates. Only live locations are edited, so the first
+/// location update starts tracking the message and sends the live results
+/// message, and the next ones refresh it until the live period ends.
+pub async fn handle(
+    context: &DispatcherHandlerCx<Message>,
+    storage: &Storage,
+    provider: &Provider,
+) {
+    let DispatcherHandlerCx { update, bot } = &context;
+    let location = match update.location() {
+        Some(location) => location,
+        None => return,
+    };
+    if is_final_update(update) {
+        if let Err(err) = forget(storage, update).await {
+            log::error!("Error deleting live location {:?}", err);
+        }
+        return;
+    }
+    let mut live_location = match load(storage, update).await {
+        Ok(live_location) => live_location.unwrap_or_default(),
+        Err(err) => {
+            log::error!("Error loading live location {:?}", err);
+            return;
+        }
+    };
+    let now = Utc::now();
+    let recently_refreshed = live_location.refreshed_at.is_some_and(|refreshed_at| {
+        now.timestamp() - refreshed_at.timestamp() < LIVE_REFRESH_SECONDS
+    });
+    if recently_refreshed {
+        return;
+    }
+
+    let language_code = update.from().and_then(|user| user.language_code.as_deref());
+    let lang = i18n::language(storage, update.chat_id(), language_code).await;
+    let text = match live_message(storage, provider, update.chat_id(), location, lang).await {
+        Ok(text) => text,
+        Err(err) => {
+            log::error!("Error fetching live location stations {:?}", err);
+            return;
+        }
+    };
+
+    match live_location.results_message_id {
+        Some(message_id) if text != live_location.text => {
+            bot.edit_message_text(
+                ChatOrInlineMessage::Chat {
+                    chat_id: ChatId::Id(update.chat_id()),
+                    message_id,
+                },
+                text.clone(),
+            )
+            .parse_mode(ParseMode::MarkdownV2)
+            .disable_web_page_preview(true)
+            .send()
+            .await
+            .log_on_error()
+            .await;
+        }
+        Some(_) => {}
+        None => {
+            // Concurrent first updates all see no results message yet
+            match claim_results_message(storage, update).await {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(err) => {
+                    log::error!("Error claiming live location message {:?}", err);
+                    return;
+                }
+            }
+            let sent = context
+                .answer(text.clone())
+                .parse_mode(ParseMode::MarkdownV2)
+                .disable_web_page_preview(true)
+                .disable_notification(true)
+                .send()
+                .await;
+            match sent {
+                Ok(sent) => live_location.results_message_id = Some(sent.id),
+                Err(err) => {
+                    log::error!("Error sending live location message {:?}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    live_location.refreshed_at = Some(now);
+    live_location.text = text;
+    if let Err(err) = save(storage, update, &live_location).await {
+        log::error!("Error saving live location {:?}", err);
+    }
+}
+
+async fn live_message(
+    storage: &Storage,
+    provider: &Provider,
+    chat_id: i64,
+    location: &Location,
+    lang: Lang,
+) -> Result<String> {
+    let user_location = geoutils::Location::new(location.latitude, location.longitude);
+    let mode = load_station_mode(storage, chat_id).await?;
+    let mut stations = find_near_stations(provider, location, LIVE_STATIONS_TAKE, mode)
+        .await
+        .map_err(|err| anyhow!(err))?;
+    prediction::annotate(storage, chat_id, stations.iter_mut()).await;
+    let mut sections = vec![escape(lang.catalog().live_title)];
+    sections.extend(
+        stations
+            .iter()
+            .map(|station| station.message(Some(&user_location), lang)),
+    );
+    Ok(sections.join("\n\n"))
+}
+
+async fn load(storage: &Storage, message: &Message) -> Result<Option<LiveLocation>> {
+    let live_location = match storage.get_optional(&live_location_key(message)).await? {
+        Some(data) => Some(serde_json::from_str(&data)?),
+        None => None,
+    };
+    Ok(live_location)
+}
+
+async fn save(storage: &Storage, message: &Message, live_location: &LiveLocation) -> Result<()> {
+    let data = serde_json::to_string(live_location)?;
+    storage
+        .set_multiple(
+            &[(live_location_key(message), data)],
+            Some(remaining_seconds(message)),
+        )
+        .await
+}
+
+/// Whether this update is the one sending the results message.
+async fn claim_results_message(storage: &Storage, message: &Message) -> Result<bool> {
+    let key = results_message_key(message);
+    let mut batch = Batch::when(Condition::Absent(key.clone()));
+    batch.set(&key, "", Some(remaining_seconds(message)));
+    storage.apply(batch).await
+}
+
+async fn forget(storage: &Storage, message: &Message) -> Result<()> {
+    let mut batch = Batch::new();
+    batch
+        .del(&live_location_key(message))
+        .del(&results_message_key(message));
+    storage.apply(batch).await.map(|_| ())
+}
+
+/// Seconds the location of `message` is shared for. teloxide doesn't parse
+/// the `live_period` of locations, so it's the longest period Telegram allows.
+fn live_period(_message: &Message) -> i64 {
+    MAX_LIVE_PERIOD
+}
+
+/// Timestamp when sharing the location of `message` ends.
+fn live_ends_at(message: &Message) -> i64 {
+    i64::from(message.date) + live_period(message)
+}
+
+/// Telegram edits the message once more when the live period ends.
+fn is_final_update(message: &Message) -> bool {
+    message
+        .edit_date()
+        .is_some_and(|edit_date| i64::from(*edit_date) >= live_ends_at(message))
+}
+
+/// Until the live period of `message` ends, dropping its tracking then.
+fn remaining_seconds(message: &Message) -> usize {
+    (live_ends_at(message) - Utc::now().timestamp()).max(1) as usize
+}
+
+fn live_location_key(message: &Message) -> String {
+    format!("{}:{}:{}", LIVE_LOCATION, message.chat_id(), message.id)
+}
+
+fn results_message_key(message: &Message) -> String {
+    format!(
+        "{}:{}:{}",
+        LIVE_LOCATION_RESULTS,
+        message.chat_id(),
+        message.id
+    )
+}
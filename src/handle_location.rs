@@ -1,21 +1,40 @@
 use crate::bike_service;
-use bike_service::{Geo, Station};
+use bike_service::station_index::StationIndex;
+use bike_service::{Geo, Provider, Station};
+use chrono::prelude::*;
+use futures::future::join_all;
+use futures::join;
 use teloxide::dispatching::DispatcherHandlerCx;
 use teloxide::error_handlers::OnError;
 use teloxide::requests::Request;
-use teloxide::types::Message;
-use teloxide::utils::markdown::{escape, italic, link};
-const SMALL_BIKE_AMOUNT: u32 = 6;
-const STATION_MAX_TAKE: usize = 5;
-const STATION_MIN_TAKE: usize = 3;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Message};
+use teloxide::utils::markdown::{bold, escape, italic, link};
+const PAGE_SIZE: usize = 5;
+const NETWORK_REACH: f64 = 30_000.0; // meters
 const GOOGLE_MAPS_URL: &str = "https://www.google.com/maps";
-use crate::station_low_warn::reply_markups;
-use std::f64::INFINITY;
+const STATION_MODE: &str = "STATION_MODE";
+use crate::handle_callback_query::{new_callback_uuid, save_callback_data};
+use crate::handle_favorites::save_buttons;
+use crate::i18n::{fill, Lang};
+use crate::models::{CallbackData, StationMode};
+use crate::prediction;
+use crate::search::save_last_network;
+use crate::station_low_warn::remind_buttons;
+use crate::station_map;
+use crate::store::Storage;
+use anyhow::Result;
 use surf::Exception;
-use teloxide::types::{Location, ParseMode};
+use teloxide::types::{InputFile, Location, ParseMode};
+use tokio::task;
 use url::Url;
+use uuid::Uuid;
 
-pub async fn handle(context: &DispatcherHandlerCx<Message>) {
+pub async fn handle(
+    context: &DispatcherHandlerCx<Message>,
+    storage: &Storage,
+    provider: &Provider,
+    lang: Lang,
+) {
     let DispatcherHandlerCx {
         update: message, ..
     } = &context;
@@ -26,12 +45,18 @@ pub async fn handle(context: &DispatcherHandlerCx<Message>) {
         return;
     };
 
-    let stations = match find_near_stations(location).await {
+    let mode = load_station_mode(storage, message.chat_id())
+        .await
+        .unwrap_or_else(|err| {
+            log::error!("Error loading station mode {:?}", err);
+            StationMode::default()
+        });
+    let stations = match page_stations(provider, location, 0, mode).await {
         Ok(stations) => stations,
         Err(err) => {
             log::error!("Error fetching stations {:?}", err);
             context
-                .answer("There was a problem to list stations")
+                .answer(lang.catalog().list_stations_error)
                 .send()
                 .await
                 .log_on_error()
@@ -40,56 +65,260 @@ pub async fn handle(context: &DispatcherHandlerCx<Message>) {
         }
     };
 
-    // Calculate take value, to see if we iter 3 or 5 stations
-    let is_small_amount: bool = stations
-        .iter()
-        .take(STATION_MIN_TAKE as usize)
-        .map(|s| s.free_bikes.unwrap_or_default()) // defaults to 0
-        .sum::<u32>()
-        <= SMALL_BIKE_AMOUNT;
-    let take = if is_small_amount {
-        STATION_MAX_TAKE
-    } else {
-        STATION_MIN_TAKE
-    };
-    let stations: Vec<Station> = stations.into_iter().take(take).collect();
+    // Remember the network for searches without a location
+    let network_href = stations.first().and_then(|s| s.network_href.as_ref());
+    if let (Some(user), Some(network_href)) = (message.from(), network_href) {
+        if let Err(err) = save_last_network(storage, user.id, network_href).await {
+            log::error!("Error saving last network {:?}", err);
+        }
+    }
 
-    let send_messages: Vec<_> = stations
-        .iter()
-        .map(|station| {
+    match page_message(
+        storage,
+        message.chat_id(),
+        location,
+        0,
+        mode,
+        &stations,
+        lang,
+    )
+    .await
+    {
+        Ok((text, keyboard)) => {
             context
-                .answer(station.message())
+                .answer(text)
                 .parse_mode(ParseMode::MarkdownV2)
                 .disable_web_page_preview(true)
-                .disable_notification(true)
+                .reply_markup(keyboard)
+                .send()
+                .await
+                .log_on_error()
+                .await
+        }
+        Err(err) => log::error!("Error building stations page {:?}", err),
+    }
+
+    let first_page = &stations[..stations.len().min(PAGE_SIZE)];
+    if let Err(err) = send_map(context, location, first_page, mode, lang).await {
+        log::error!("Error sending stations map {:?}", err);
+    }
+}
+
+/// Renders the stations map to a temporary file, Telegram uploads photos only
+/// from files. The file is removed whether the upload succeeds or not.
+async fn send_map(
+    context: &DispatcherHandlerCx<Message>,
+    location: &Location,
+    stations: &[Station],
+    mode: StationMode,
+    lang: Lang,
+) -> Result<()> {
+    let user_location = geoutils::Location::new(location.latitude, location.longitude);
+    let stations = stations.to_vec();
+    let path = std::env::temp_dir().join(format!("stations-map-{}.png", Uuid::new_v4()));
+    let map_path = path.clone();
+    let sent: Result<()> = async {
+        task::spawn_blocking(move || -> Result<()> {
+            let data = station_map::render(&user_location, &stations, 1, mode)?;
+            std::fs::write(&map_path, data)?;
+            Ok(())
         })
-        .collect();
-    let reply_markups = reply_markups(&stations).await;
-    let send_messages: Vec<_> = if let Ok(reply_markups) = reply_markups {
-        send_messages
-            .into_iter()
-            .zip(reply_markups.into_iter())
-            .map(|(send_message, reply_markup)| match reply_markup {
-                Some(rm) => send_message.reply_markup(rm),
-                None => send_message,
-            })
-            .collect()
+        .await??;
+        context
+            .answer_photo(InputFile::File(path.clone()))
+            .caption(lang.catalog().stations_map)
+            .disable_notification(true)
+            .send()
+            .await?;
+        Ok(())
+    }
+    .await;
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    sent
+}
+
+pub async fn load_station_mode(storage: &Storage, chat_id: i64) -> Result<StationMode> {
+    let mode = match storage.get_optional(&station_mode_key(chat_id)).await? {
+        Some(data) => serde_json::from_str(&data)?,
+        None => StationMode::default(),
+    };
+    Ok(mode)
+}
+
+pub async fn save_station_mode(storage: &Storage, chat_id: i64, mode: StationMode) -> Result<()> {
+    let data = serde_json::to_string(&mode)?;
+    storage
+        .set_multiple(&[(station_mode_key(chat_id), data)], None)
+        .await
+}
+
+fn station_mode_key(chat_id: i64) -> String {
+    format!("{}:{}", STATION_MODE, chat_id)
+}
+
+/// Stations up to `page`, plus one more telling whether there is a next page.
+pub async fn page_stations(
+    provider: &Provider,
+    location: &Location,
+    page: usize,
+    mode: StationMode,
+) -> Result<Vec<Station>, Exception> {
+    find_near_stations(provider, location, (page + 1) * PAGE_SIZE + 1, mode).await
+}
+
+/// Compact listing of the `page` stations, with their reminder and save
+/// buttons numbered after their position, and buttons to move between pages
+/// or change the station mode.
+pub async fn page_message(
+    storage: &Storage,
+    chat_id: i64,
+    location: &Location,
+    page: usize,
+    mode: StationMode,
+    stations: &[Station],
+    lang: Lang,
+) -> Result<(String, InlineKeyboardMarkup)> {
+    let catalog = lang.catalog();
+    let user_location = geoutils::Location::new(location.latitude, location.longitude);
+    let start = (page * PAGE_SIZE).min(stations.len());
+    let end = ((page + 1) * PAGE_SIZE).min(stations.len());
+    let mut page_stations = stations[start..end].to_vec();
+    prediction::annotate(storage, chat_id, page_stations.iter_mut()).await;
+
+    let title = fill(catalog.stations_page, &[("page", &(page + 1).to_string())]);
+    let mut sections = vec![escape(&title)];
+    sections.extend(
+        page_stations.iter().enumerate().map(|(index, station)| {
+            station.compact_message(start + index + 1, &user_location, lang)
+        }),
+    );
+
+    let rows = station_rows(storage, chat_id, &page_stations, Some(&user_location), lang).await;
+    let keyboard = rows.into_iter().enumerate().fold(
+        InlineKeyboardMarkup::default(),
+        |keyboard, (index, row)| {
+            let row = row
+                .into_iter()
+                .map(|mut button| {
+                    button.text = format!("{}. {}", start + index + 1, button.text);
+                    button
+                })
+                .collect::<Vec<_>>();
+            if row.is_empty() {
+                keyboard
+            } else {
+                keyboard.append_row(row)
+            }
+        },
+    );
+
+    let mut page_buttons = vec![];
+    let mut callback_data = vec![];
+    let mut page_button = |text: &str, page: usize| {
+        let uuid = new_callback_uuid();
+        page_buttons.push(InlineKeyboardButton::callback(text.into(), uuid.clone()));
+        callback_data.push((
+            uuid,
+            CallbackData::StationsPage {
+                location: *location,
+                page,
+            },
+        ));
+    };
+    if page > 0 {
+        page_button(catalog.previous_button, page - 1);
+    }
+    if stations.len() > end {
+        page_button(catalog.next_button, page + 1);
+    }
+    let keyboard = if page_buttons.is_empty() {
+        keyboard
     } else {
-        log::error!("Error creating reply markups");
-        send_messages
+        keyboard.append_row(page_buttons)
     };
 
-    for send_message in send_messages {
-        send_message.send().await.log_on_error().await;
+    // Tapping the selected mode goes back to every station
+    let mut mode_buttons = vec![];
+    for (text, button_mode) in &[
+        (catalog.need_bike_button, StationMode::NeedBike),
+        (catalog.need_slot_button, StationMode::NeedSlot),
+    ] {
+        let (text, new_mode) = if mode == *button_mode {
+            (format!("✓ {}", text), StationMode::Any)
+        } else {
+            (text.to_string(), *button_mode)
+        };
+        let uuid = new_callback_uuid();
+        mode_buttons.push(InlineKeyboardButton::callback(text, uuid.clone()));
+        callback_data.push((
+            uuid,
+            CallbackData::SetStationMode {
+                location: *location,
+                mode: new_mode,
+            },
+        ));
     }
+    save_callback_data(storage, callback_data).await?;
+    Ok((sections.join("\n\n"), keyboard.append_row(mode_buttons)))
+}
+
+/// One row per station with its reminder and "★ Save" buttons.
+pub async fn reply_markups(
+    storage: &Storage,
+    chat_id: i64,
+    stations: &[Station],
+    user_location: Option<&geoutils::Location>,
+    lang: Lang,
+) -> Vec<Option<InlineKeyboardMarkup>> {
+    station_rows(storage, chat_id, stations, user_location, lang)
+        .await
+        .into_iter()
+        .map(|row| {
+            if row.is_empty() {
+                None
+            } else {
+                Some(InlineKeyboardMarkup::default().append_row(row))
+            }
+        })
+        .collect()
+}
+
+async fn station_rows(
+    storage: &Storage,
+    chat_id: i64,
+    stations: &[Station],
+    user_location: Option<&geoutils::Location>,
+    lang: Lang,
+) -> Vec<Vec<InlineKeyboardButton>> {
+    let (remind_buttons, save_buttons) = join!(
+        remind_buttons(storage, chat_id, stations, user_location, lang),
+        save_buttons(storage, stations, lang)
+    );
+    let remind_buttons = remind_buttons.unwrap_or_else(|err| {
+        log::error!("Error creating remind buttons {:?}", err);
+        vec![]
+    });
+    let save_buttons = save_buttons.unwrap_or_else(|err| {
+        log::error!("Error creating save buttons {:?}", err);
+        vec![]
+    });
+    (0..stations.len())
+        .map(|index| {
+            let mut row: Vec<InlineKeyboardButton> =
+                remind_buttons.get(index).cloned().unwrap_or_default();
+            row.extend(save_buttons.get(index).cloned().flatten());
+            row
+        })
+        .collect()
 }
 
 impl Station {
-    fn message(&self) -> String {
-        let mut url = Url::parse(GOOGLE_MAPS_URL).unwrap();
-        url.query_pairs_mut()
-            .append_pair("q", &format!("{},{}", &self.latitude, &self.longitude));
-        let name = link(url.as_str(), &escape(&self.name));
+    /// MarkdownV2 station card. Distance is shown when the user location is known.
+    pub fn message(&self, user_location: Option<&geoutils::Location>, lang: Lang) -> String {
+        let catalog = lang.catalog();
+        let name = link(&self.maps_url(), &escape(&self.name));
         let free_bikes = self
             .free_bikes
             .map_or(String::from("??"), |num| num.to_string());
@@ -99,45 +328,210 @@ impl Station {
         let description = self
             .extra
             .as_ref()
-            .and_then(|extra| {
-                extra
-                    .description
-                    .as_ref()
-                    .or_else(|| extra.address.as_ref())
-            })
+            .and_then(|extra| extra.description.as_ref().or(extra.address.as_ref()))
             .cloned()
             .unwrap_or_default();
         let description = italic(&escape(&description));
-        format!(
-            "`Station   :` {}
-`Bikes     :` {}
-`Free slot :` {}
-{}",
-            name, free_bikes, empty_slots, description
-        )
+        let row = |label: &str, value: &str| format!("`{:<10}:` {}", label, value);
+
+        let mut rows = vec![row(catalog.station_label, &name)];
+        if let Some(user_location) = user_location {
+            let walk = escape(&self.walk_from(user_location).to_string());
+            rows.push(row(catalog.distance_label, &walk));
+        }
+        match (&self.prediction, self.free_bikes) {
+            (Some(prediction), Some(current)) => {
+                let forecast = prediction.free_bikes;
+                let trend = format!("{} {}", free_bikes, forecast.trend(current));
+                let value = fill(
+                    catalog.forecast_value,
+                    &[
+                        ("in_15", &forecast.in_15.to_string()),
+                        ("in_30", &forecast.in_30.to_string()),
+                    ],
+                );
+                rows.push(row(catalog.bikes_label, &trend));
+                rows.push(row(catalog.forecast_label, &escape(&value)));
+            }
+            _ => rows.push(row(catalog.bikes_label, &free_bikes)),
+        }
+        if let (Some(ebikes), Some(normal_bikes)) = (self.ebikes(), self.normal_bikes()) {
+            let value = fill(
+                catalog.ebikes_value,
+                &[
+                    ("ebikes", &ebikes.to_string()),
+                    ("normal", &normal_bikes.to_string()),
+                ],
+            );
+            rows.push(row(catalog.ebikes_label, &escape(&value)));
+        }
+        rows.push(row(catalog.free_slots_label, &empty_slots));
+        rows.push(row(
+            catalog.updated_label,
+            &escape(&self.updated_text(lang)),
+        ));
+        rows.extend(
+            self.status_notes(lang)
+                .into_iter()
+                .map(|note| bold(&escape(note))),
+        );
+        rows.push(description);
+        rows.join("\n")
+    }
+
+    /// MarkdownV2 two line summary, for listings with several stations.
+    pub fn compact_message(
+        &self,
+        position: usize,
+        user_location: &geoutils::Location,
+        lang: Lang,
+    ) -> String {
+        let catalog = lang.catalog();
+        let amount = |value: Option<u32>| value.map_or(String::from("??"), |num| num.to_string());
+        let name = link(&self.maps_url(), &escape(&self.name));
+        let free_bikes = match (&self.prediction, self.free_bikes) {
+            (Some(prediction), Some(current)) => {
+                format!("{} {}", current, prediction.free_bikes.trend(current))
+            }
+            _ => amount(self.free_bikes),
+        };
+        let free_bikes = match self.ebikes() {
+            Some(ebikes) => format!("{} (⚡{})", free_bikes, ebikes),
+            None => free_bikes,
+        };
+        let counts = format!(
+            "{}: {} · {}: {} · {}",
+            catalog.bikes_label,
+            free_bikes,
+            catalog.free_slots_label,
+            amount(self.empty_slots),
+            self.walk_from(user_location)
+        );
+        let mut notes = self.status_notes(lang);
+        if self.is_stale() {
+            notes.push(catalog.stale_data);
+        }
+        let counts = std::iter::once(counts.as_str())
+            .chain(notes)
+            .collect::<Vec<_>>()
+            .join(" · ");
+        format!("{}\\. {}\n{}", position, name, escape(&counts))
+    }
+
+    /// Warnings about the station not working as usual.
+    fn status_notes(&self, lang: Lang) -> Vec<&'static str> {
+        let catalog = lang.catalog();
+        if self.is_closed() {
+            return vec![catalog.station_closed];
+        }
+        let mut notes = vec![];
+        if !self.is_renting() {
+            notes.push(catalog.not_renting);
+        }
+        if !self.is_returning() {
+            notes.push(catalog.not_returning);
+        }
+        notes
+    }
+
+    /// `3 min ago` under an hour, `2 h ago` above it, flagged when stale.
+    fn updated_text(&self, lang: Lang) -> String {
+        let catalog = lang.catalog();
+        let minutes = ((Utc::now().timestamp() - self.timestamp.timestamp()) / 60).max(0);
+        let text = if minutes < 60 {
+            fill(catalog.updated_minutes, &[("amount", &minutes.to_string())])
+        } else {
+            fill(
+                catalog.updated_hours,
+                &[("amount", &(minutes / 60).to_string())],
+            )
+        };
+        if self.is_stale() {
+            format!("{} {}", text, catalog.stale_data)
+        } else {
+            text
+        }
+    }
+
+    fn maps_url(&self) -> String {
+        let mut url = Url::parse(GOOGLE_MAPS_URL).unwrap();
+        url.query_pairs_mut()
+            .append_pair("q", &format!("{},{}", &self.latitude, &self.longitude));
+        url.into_string()
     }
 }
 
-async fn find_near_stations(location: &Location) -> Result<Vec<Station>, Exception> {
+/// Stations closest to `location` among every network within reach, so users
+/// near a city boundary also see the neighbouring operator. Stations `mode`
+/// doesn't accept are skipped.
+pub async fn find_near_stations(
+    provider: &Provider,
+    location: &Location,
+    take: usize,
+    mode: StationMode,
+) -> Result<Vec<Station>, Exception> {
     let user_location = geoutils::Location::new(location.latitude, location.longitude);
-    let mut networks = bike_service::fetch_networks().await?;
+    let mut stations = near_network_stations(provider, location).await?;
+    stations.retain(|station| mode.accepts(station));
+    let index = StationIndex::new(stations);
+    Ok(index
+        .nearest(&user_location, take, NETWORK_REACH)
+        .into_iter()
+        .cloned()
+        .collect())
+}
+
+/// Every station of the networks within reach of `location`. Fails only when
+/// no station could be fetched at all.
+pub async fn near_network_stations(
+    provider: &Provider,
+    location: &Location,
+) -> Result<Vec<Station>, Exception> {
+    let user_location = geoutils::Location::new(location.latitude, location.longitude);
+    let mut networks = provider.networks().await?;
     networks.sort_by_key(|network| {
         user_location
-            .distance_to(&network.location())
-            .unwrap_or_else(|_| geoutils::Distance::from_meters(INFINITY))
-            .meters() as u32
-    });
-    let mut stations = if let Some(network) = networks.first() {
-        log::debug!("Closest bike network, {}", network.name);
-        network.stations().await?
-    } else {
-        vec![]
-    };
-    stations.sort_by_key(|station| {
-        user_location
-            .distance_to(&station.location())
-            .unwrap_or_else(|_| geoutils::Distance::from_meters(INFINITY))
+            .haversine_distance_to(&network.location())
             .meters() as u32
     });
+    let near_networks: Vec<_> = networks
+        .iter()
+        .enumerate()
+        .filter(|(index, network)| {
+            *index == 0
+                || user_location
+                    .haversine_distance_to(&network.location())
+                    .meters()
+                    <= NETWORK_REACH
+        })
+        .map(|(_, network)| network)
+        .collect();
+    log::debug!(
+        "Near bike networks: {:?}",
+        near_networks.iter().map(|n| &n.name).collect::<Vec<_>>()
+    );
+
+    let results = join_all(
+        near_networks
+            .iter()
+            .map(|network| network.stations(provider.as_ref())),
+    )
+    .await;
+    let mut stations: Vec<Station> = vec![];
+    let mut last_error = None;
+    for result in results {
+        match result {
+            Ok(network_stations) => stations.extend(network_stations),
+            Err(err) => {
+                log::error!("Error fetching network stations {:?}", err);
+                last_error = Some(err);
+            }
+        }
+    }
+    if stations.is_empty() {
+        if let Some(err) = last_error {
+            return Err(err);
+        }
+    }
     Ok(stations)
 }
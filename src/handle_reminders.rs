@@ -0,0 +1,87 @@
+use crate::handle_callback_query::{new_callback_uuid, save_callback_data};
+use crate::i18n::{fill, Lang};
+use crate::models::{CallbackData, WarnKind};
+use crate::station_low_warn::chat_station_warns;
+use crate::store::Storage;
+use anyhow::Result;
+use teloxide::dispatching::DispatcherHandlerCx;
+use teloxide::error_handlers::OnError;
+use teloxide::requests::Request;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Message};
+
+/// `/reminders`, lists the chat active station warns.
+pub async fn handle(context: &DispatcherHandlerCx<Message>, storage: &Storage, lang: Lang) {
+    match reminders_message(storage, context.update.chat_id(), lang).await {
+        Ok((text, keyboard)) => {
+            let send_message = context.answer(text);
+            let send_message = match keyboard {
+                Some(keyboard) => send_message.reply_markup(keyboard),
+                None => send_message,
+            };
+            send_message.send().await.log_on_error().await;
+        }
+        Err(err) => {
+            log::error!("Error listing reminders {:?}", err);
+            context
+                .answer(lang.catalog().generic_error)
+                .send()
+                .await
+                .log_on_error()
+                .await;
+        }
+    }
+}
+
+/// Reminders list with remaining time and a "Stop" button for each one.
+pub async fn reminders_message(
+    storage: &Storage,
+    chat_id: i64,
+    lang: Lang,
+) -> Result<(String, Option<InlineKeyboardMarkup>)> {
+    let catalog = lang.catalog();
+    let station_warns = chat_station_warns(storage, chat_id).await?;
+    if station_warns.is_empty() {
+        return Ok((catalog.no_reminders.to_string(), None));
+    }
+
+    let mut lines = vec![catalog.reminders_title.to_string()];
+    let mut buttons = vec![];
+    let mut callback_data = vec![];
+    for (index, station_warn) in station_warns.iter().enumerate() {
+        let position = (index + 1).to_string();
+        let kind = match station_warn.kind {
+            WarnKind::Bikes => catalog.kind_bikes,
+            WarnKind::Slots => catalog.kind_slots,
+            WarnKind::EBikes => catalog.kind_ebikes,
+        };
+        let minutes = (station_warn.remaining_seconds() / 60 + 1).to_string();
+        lines.push(fill(
+            catalog.reminder_line,
+            &[
+                ("position", &position),
+                ("station", &station_warn.station_info.name),
+                ("kind", kind),
+                ("minutes", &minutes),
+            ],
+        ));
+
+        let uuid = new_callback_uuid();
+        let text = format!("{} {}", catalog.stop_button, position);
+        buttons.push(InlineKeyboardButton::callback(text, uuid.clone()));
+        callback_data.push((
+            uuid,
+            CallbackData::StopStationReminder {
+                warn_uuid: station_warn.uuid.clone(),
+                refresh_list: true,
+            },
+        ));
+    }
+    save_callback_data(storage, callback_data).await?;
+
+    let keyboard = buttons
+        .chunks(4)
+        .fold(InlineKeyboardMarkup::default(), |keyboard, row| {
+            keyboard.append_row(row.to_vec())
+        });
+    Ok((lines.join("\n"), Some(keyboard)))
+}
@@ -0,0 +1,261 @@
+use crate::bike_service::{self, Provider};
+use crate::commute_alert::{chat_commute_alerts, save_commute_alert};
+use crate::handle_callback_query::{new_callback_uuid, save_callback_data};
+use crate::i18n::{fill, Lang};
+use crate::models::{CallbackData, CommuteAlert, StationReminderInfo};
+use crate::search::{last_network, search};
+use crate::store::Storage;
+use anyhow::{anyhow, Result};
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use std::convert::TryFrom;
+use teloxide::dispatching::DispatcherHandlerCx;
+use teloxide::error_handlers::OnError;
+use teloxide::requests::Request;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Message};
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// `/schedule <days> <HH:MM> <timezone> <station>`, creates a recurring alert
+/// for the best match of `<station>` on the user last network.
+pub async fn handle(
+    context: &DispatcherHandlerCx<Message>,
+    storage: &Storage,
+    provider: &Provider,
+    lang: Lang,
+) {
+    let catalog = lang.catalog();
+    let message = &context.update;
+    let text = message.text().unwrap_or_default();
+    let (weekdays, time, tz, query) = match parse_schedule(text) {
+        Some(parsed) => parsed,
+        None => {
+            context
+                .answer(catalog.schedule_usage)
+                .send()
+                .await
+                .log_on_error()
+                .await;
+            return;
+        }
+    };
+    let network_href = match message.from() {
+        Some(user) => last_network(storage, user.id).await.unwrap_or_else(|err| {
+            log::error!("Error loading last network {:?}", err);
+            None
+        }),
+        None => None,
+    };
+    let network_href = match network_href {
+        Some(network_href) => network_href,
+        None => {
+            crate::handle_start(context, lang).await;
+            return;
+        }
+    };
+
+    let stations = match provider.stations(&network_href).await {
+        Ok(stations) => stations,
+        Err(err) => {
+            log::error!("Error fetching stations {:?}", err);
+            context
+                .answer(catalog.list_stations_error)
+                .send()
+                .await
+                .log_on_error()
+                .await;
+            return;
+        }
+    };
+    let station = match search(&stations, &query, None, 1).into_iter().next() {
+        Some(station) => station.clone(),
+        None => {
+            context
+                .answer(fill(catalog.search_no_results, &[("query", &query)]))
+                .send()
+                .await
+                .log_on_error()
+                .await;
+            return;
+        }
+    };
+
+    let result = create_alert(
+        storage,
+        message.chat_id(),
+        station,
+        weekdays,
+        time,
+        tz,
+        lang,
+    )
+    .await;
+    let text = match result {
+        Ok(alert) => fill(
+            catalog.schedule_created,
+            &[
+                ("station", &alert.station_info.name),
+                ("days", &alert.days()),
+                ("time", &alert.time.format("%H:%M").to_string()),
+                ("timezone", &alert.timezone),
+            ],
+        ),
+        Err(err) => {
+            log::error!("Error creating commute alert {:?}", err);
+            catalog.generic_error.to_string()
+        }
+    };
+    context.answer(text).send().await.log_on_error().await;
+}
+
+/// `/schedules`, lists the chat alerts with a "Delete" button for each one.
+pub async fn handle_list(context: &DispatcherHandlerCx<Message>, storage: &Storage, lang: Lang) {
+    match schedules_message(storage, context.update.chat_id(), lang).await {
+        Ok((text, keyboard)) => {
+            let send_message = context.answer(text);
+            let send_message = match keyboard {
+                Some(keyboard) => send_message.reply_markup(keyboard),
+                None => send_message,
+            };
+            send_message.send().await.log_on_error().await;
+        }
+        Err(err) => {
+            log::error!("Error listing commute alerts {:?}", err);
+            context
+                .answer(lang.catalog().generic_error)
+                .send()
+                .await
+                .log_on_error()
+                .await;
+        }
+    }
+}
+
+pub async fn schedules_message(
+    storage: &Storage,
+    chat_id: i64,
+    lang: Lang,
+) -> Result<(String, Option<InlineKeyboardMarkup>)> {
+    let catalog = lang.catalog();
+    let alerts = chat_commute_alerts(storage, chat_id).await?;
+    if alerts.is_empty() {
+        return Ok((catalog.no_schedules.to_string(), None));
+    }
+
+    let mut lines = vec![catalog.schedules_title.to_string()];
+    let mut buttons = vec![];
+    let mut callback_data = vec![];
+    for (index, alert) in alerts.iter().enumerate() {
+        let position = (index + 1).to_string();
+        lines.push(fill(
+            catalog.schedule_line,
+            &[
+                ("position", &position),
+                ("station", &alert.station_info.name),
+                ("days", &alert.days()),
+                ("time", &alert.time.format("%H:%M").to_string()),
+                ("timezone", &alert.timezone),
+            ],
+        ));
+
+        let uuid = new_callback_uuid();
+        let text = format!("{} {}", catalog.delete_button, position);
+        buttons.push(InlineKeyboardButton::callback(text, uuid.clone()));
+        callback_data.push((
+            uuid,
+            CallbackData::DeleteCommuteAlert {
+                alert_uuid: alert.uuid.clone(),
+            },
+        ));
+    }
+    save_callback_data(storage, callback_data).await?;
+
+    let keyboard = buttons
+        .chunks(4)
+        .fold(InlineKeyboardMarkup::default(), |keyboard, row| {
+            keyboard.append_row(row.to_vec())
+        });
+    Ok((lines.join("\n"), Some(keyboard)))
+}
+
+async fn create_alert(
+    storage: &Storage,
+    chat_id: i64,
+    station: bike_service::Station,
+    weekdays: Vec<Weekday>,
+    time: NaiveTime,
+    tz: Tz,
+    lang: Lang,
+) -> Result<CommuteAlert> {
+    let station_info = StationReminderInfo::try_from(station)?;
+    let alert = CommuteAlert {
+        uuid: station_info.uuid.clone(),
+        chat_id,
+        station_info,
+        weekdays,
+        time,
+        timezone: tz.name().to_string(),
+        lang,
+    };
+    if alert.next_run_at(Utc::now()).is_none() {
+        return Err(anyhow!("Commute alert without a next run"));
+    }
+    save_commute_alert(storage, &alert).await?;
+    Ok(alert)
+}
+
+/// Days, time, timezone and station query of a `/schedule` command.
+fn parse_schedule(text: &str) -> Option<(Vec<Weekday>, NaiveTime, Tz, String)> {
+    let mut words = text.split_whitespace().skip(1);
+    let weekdays = parse_weekdays(words.next()?)?;
+    let time = NaiveTime::parse_from_str(words.next()?, "%H:%M").ok()?;
+    let tz: Tz = words.next()?.parse().ok()?;
+    let query = words.collect::<Vec<_>>().join(" ");
+    if query.is_empty() {
+        return None;
+    }
+    Some((weekdays, time, tz, query))
+}
+
+/// `mon,wed,fri`, `mon-fri`, `weekdays`, `weekend` or `daily`, in week order.
+fn parse_weekdays(text: &str) -> Option<Vec<Weekday>> {
+    let mut weekdays = vec![];
+    for part in text.to_lowercase().split(',') {
+        match part {
+            "daily" => weekdays.extend(&WEEKDAYS),
+            "weekdays" => weekdays.extend(&WEEKDAYS[..5]),
+            "weekend" => weekdays.extend(&WEEKDAYS[5..]),
+            _ => {
+                let mut range = part.splitn(2, '-');
+                let first: Weekday = range.next()?.parse().ok()?;
+                let last: Weekday = match range.next() {
+                    Some(last) => last.parse().ok()?,
+                    None => first,
+                };
+                let mut weekday = first;
+                weekdays.push(weekday);
+                while weekday != last {
+                    weekday = weekday.succ();
+                    weekdays.push(weekday);
+                }
+            }
+        }
+    }
+    let weekdays: Vec<Weekday> = WEEKDAYS
+        .iter()
+        .filter(|weekday| weekdays.contains(weekday))
+        .cloned()
+        .collect();
+    if weekdays.is_empty() {
+        None
+    } else {
+        Some(weekdays)
+    }
+}
@@ -0,0 +1,80 @@
+use crate::bike_service::Provider;
+use crate::handle_location::reply_markups;
+use crate::i18n::{fill, Lang};
+use crate::prediction;
+use crate::search::{last_network, search};
+use crate::store::Storage;
+use teloxide::dispatching::DispatcherHandlerCx;
+use teloxide::error_handlers::OnError;
+use teloxide::requests::Request;
+use teloxide::types::{Message, ParseMode};
+const SEARCH_RESULTS_LIMIT: usize = 3;
+
+/// Free text, searched as a station name or address on the network of the
+/// last location the user sent. Without one it asks for a location.
+pub async fn handle(
+    context: &DispatcherHandlerCx<Message>,
+    storage: &Storage,
+    provider: &Provider,
+    lang: Lang,
+) {
+    let catalog = lang.catalog();
+    let message = &context.update;
+    let query = message.text().unwrap_or_default();
+    let network_href = match message.from() {
+        Some(user) => last_network(storage, user.id).await.unwrap_or_else(|err| {
+            log::error!("Error loading last network {:?}", err);
+            None
+        }),
+        None => None,
+    };
+    let network_href = match network_href {
+        Some(network_href) => network_href,
+        None => {
+            crate::handle_start(context, lang).await;
+            return;
+        }
+    };
+
+    let stations = match provider.stations(&network_href).await {
+        Ok(stations) => stations,
+        Err(err) => {
+            log::error!("Error fetching stations {:?}", err);
+            context
+                .answer(catalog.list_stations_error)
+                .send()
+                .await
+                .log_on_error()
+                .await;
+            return;
+        }
+    };
+    let mut found: Vec<_> = search(&stations, query, None, SEARCH_RESULTS_LIMIT)
+        .into_iter()
+        .cloned()
+        .collect();
+    if found.is_empty() {
+        context
+            .answer(fill(catalog.search_no_results, &[("query", query)]))
+            .send()
+            .await
+            .log_on_error()
+            .await;
+        return;
+    }
+
+    prediction::annotate(storage, message.chat_id(), found.iter_mut()).await;
+    let reply_markups = reply_markups(storage, message.chat_id(), &found, None, lang).await;
+    for (station, reply_markup) in found.iter().zip(reply_markups) {
+        let send_message = context
+            .answer(station.message(None, lang))
+            .parse_mode(ParseMode::MarkdownV2)
+            .disable_web_page_preview(true)
+            .disable_notification(true);
+        let send_message = match reply_markup {
+            Some(rm) => send_message.reply_markup(rm),
+            None => send_message,
+        };
+        send_message.send().await.log_on_error().await;
+    }
+}
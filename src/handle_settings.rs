@@ -0,0 +1,155 @@
+use crate::handle_callback_query::{new_callback_uuid, save_callback_data};
+use crate::i18n::{fill, Lang};
+use crate::models::{CallbackData, WarnSettings};
+use crate::store::Storage;
+use anyhow::Result;
+use teloxide::dispatching::DispatcherHandlerCx;
+use teloxide::error_handlers::OnError;
+use teloxide::requests::Request;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Message};
+const SETTINGS: &str = "SETTINGS";
+const DURATION_OPTIONS: [u32; 4] = [15, 30, 60, 120]; // minutes
+const INTERVAL_OPTIONS: [u32; 4] = [1, 5, 10, 15]; // minutes
+const THRESHOLD_OPTIONS: [Option<u32>; 4] = [None, Some(1), Some(3), Some(5)];
+
+/// `/settings`, shows the reminder settings with a keyboard to change them.
+pub async fn handle(context: &DispatcherHandlerCx<Message>, storage: &Storage, lang: Lang) {
+    let settings = load_settings(storage, context.update.chat_id())
+        .await
+        .unwrap_or_else(|err| {
+            log::error!("Error loading settings {:?}", err);
+            WarnSettings::default()
+        });
+    match settings_message(storage, settings, lang).await {
+        Ok((text, keyboard)) => {
+            context
+                .answer(text)
+                .reply_markup(keyboard)
+                .send()
+                .await
+                .log_on_error()
+                .await
+        }
+        Err(err) => log::error!("Error building settings message {:?}", err),
+    }
+}
+
+/// Text describing `settings` and a keyboard where every button holds the
+/// settings resulting from tapping it.
+pub async fn settings_message(
+    storage: &Storage,
+    settings: WarnSettings,
+    lang: Lang,
+) -> Result<(String, InlineKeyboardMarkup)> {
+    let catalog = lang.catalog();
+    let minutes = |value: u32| format!("{} min", value);
+    let threshold = |value: Option<u32>| {
+        value.map_or(catalog.settings_off.to_string(), |value| value.to_string())
+    };
+    let text = [
+        catalog.settings_title.to_string(),
+        fill(
+            catalog.settings_duration,
+            &[("value", &minutes(settings.duration_minutes))],
+        ),
+        fill(
+            catalog.settings_interval,
+            &[("value", &minutes(settings.interval_minutes))],
+        ),
+        fill(
+            catalog.settings_below,
+            &[("value", &threshold(settings.below))],
+        ),
+        fill(
+            catalog.settings_above,
+            &[("value", &threshold(settings.above))],
+        ),
+    ]
+    .join("\n");
+
+    let rows: Vec<Vec<(String, WarnSettings)>> = vec![
+        DURATION_OPTIONS
+            .iter()
+            .map(|&value| {
+                let option = WarnSettings {
+                    duration_minutes: value,
+                    ..settings
+                };
+                (format!("⏱ {}m", value), option)
+            })
+            .collect(),
+        INTERVAL_OPTIONS
+            .iter()
+            .map(|&value| {
+                let option = WarnSettings {
+                    interval_minutes: value,
+                    ..settings
+                };
+                (format!("🔁 {}m", value), option)
+            })
+            .collect(),
+        THRESHOLD_OPTIONS
+            .iter()
+            .map(|&value| {
+                let option = WarnSettings {
+                    below: value,
+                    ..settings
+                };
+                (format!("⬇️ {}", threshold(value)), option)
+            })
+            .collect(),
+        THRESHOLD_OPTIONS
+            .iter()
+            .map(|&value| {
+                let option = WarnSettings {
+                    above: value,
+                    ..settings
+                };
+                (format!("⬆️ {}", threshold(value)), option)
+            })
+            .collect(),
+    ];
+
+    let mut callback_data = vec![];
+    let keyboard = rows
+        .into_iter()
+        .fold(InlineKeyboardMarkup::default(), |keyboard, row| {
+            let buttons = row
+                .into_iter()
+                .map(|(text, option)| {
+                    let text = if option == settings {
+                        format!("✓ {}", text)
+                    } else {
+                        text
+                    };
+                    let uuid = new_callback_uuid();
+                    callback_data.push((uuid.clone(), CallbackData::UpdateSettings(option)));
+                    InlineKeyboardButton::callback(text, uuid)
+                })
+                .collect();
+            keyboard.append_row(buttons)
+        });
+    save_callback_data(storage, callback_data).await?;
+    Ok((text, keyboard))
+}
+
+pub async fn load_settings(storage: &Storage, chat_id: i64) -> Result<WarnSettings> {
+    let data = storage.get_optional(&settings_key(chat_id)).await?;
+    let settings = match data {
+        Some(data) => serde_json::from_str(&data)?,
+        None => WarnSettings::default(),
+    };
+    Ok(settings)
+}
+
+pub async fn save_settings(storage: &Storage, chat_id: i64, settings: WarnSettings) -> Result<()> {
+    let data = serde_json::to_string(&settings)?;
+    storage
+        .set_multiple(&[(settings_key(chat_id), data)], None)
+        .await?;
+    Ok(())
+}
+
+fn settings_key(chat_id: i64) -> String {
+    format!("{}:{}", SETTINGS, chat_id)
+}
@@ -0,0 +1,216 @@
+use crate::bike_service::{Geo, Provider, Station};
+use crate::handle_callback_query::{new_callback_uuid, save_callback_data};
+use crate::handle_location::find_near_stations;
+use crate::i18n::Lang;
+use crate::models::{CallbackData, StationMode, StationReminderInfo, TripPlan};
+use crate::prediction;
+use crate::store::Storage;
+use anyhow::{anyhow, Result};
+use std::convert::TryFrom;
+use teloxide::dispatching::DispatcherHandlerCx;
+use teloxide::error_handlers::OnError;
+use teloxide::requests::Request;
+use teloxide::types::{
+    ButtonRequest, InlineKeyboardButton, InlineKeyboardMarkup, KeyboardButton, Location, Message,
+    ParseMode, ReplyKeyboardMarkup,
+};
+use teloxide::utils::markdown::{bold, escape};
+const TRIP: &str = "TRIP";
+const TRIP_TTL: usize = 60 * 30; // 30 minutes to send both locations
+const TRIP_CANDIDATES: usize = 10;
+
+/// `/trip`, starts asking for the origin location.
+pub async fn handle(context: &DispatcherHandlerCx<Message>, storage: &Storage, lang: Lang) {
+    let catalog = lang.catalog();
+    if let Err(err) = save_trip(storage, context.update.chat_id(), &TripPlan::default()).await {
+        log::error!("Error starting trip {:?}", err);
+        context
+            .answer(catalog.generic_error)
+            .send()
+            .await
+            .log_on_error()
+            .await;
+        return;
+    }
+    let location_button =
+        KeyboardButton::new(catalog.send_location_button).request(ButtonRequest::Location);
+    let keyboard = ReplyKeyboardMarkup::default()
+        .resize_keyboard(true)
+        .append_row(vec![location_button]);
+    context
+        .answer(catalog.trip_origin)
+        .reply_markup(keyboard)
+        .send()
+        .await
+        .log_on_error()
+        .await;
+}
+
+/// Takes the location when a trip is being planned. Returns false when there
+/// is none, so it's handled as a regular location.
+pub async fn handle_location(
+    context: &DispatcherHandlerCx<Message>,
+    storage: &Storage,
+    provider: &Provider,
+    lang: Lang,
+) -> bool {
+    let catalog = lang.catalog();
+    let chat_id = context.update.chat_id();
+    let location = match context.update.location() {
+        Some(location) => *location,
+        None => return false,
+    };
+    let trip = match load_trip(storage, chat_id).await {
+        Ok(Some(trip)) => trip,
+        Ok(None) => return false,
+        Err(err) => {
+            log::error!("Error loading trip {:?}", err);
+            return false;
+        }
+    };
+
+    let origin = match trip.origin {
+        Some(origin) => origin,
+        None => {
+            let trip = TripPlan {
+                origin: Some(location),
+            };
+            let text = match save_trip(storage, chat_id, &trip).await {
+                Ok(_) => catalog.trip_destination,
+                Err(err) => {
+                    log::error!("Error saving trip origin {:?}", err);
+                    catalog.generic_error
+                }
+            };
+            context.answer(text).send().await.log_on_error().await;
+            return true;
+        }
+    };
+
+    if let Err(err) = storage.del_multiple(&[trip_key(chat_id)]).await {
+        log::error!("Error clearing trip {:?}", err);
+    }
+    match trip_message(storage, provider, chat_id, &origin, &location, lang).await {
+        Ok((text, keyboard)) => {
+            let send_message = context
+                .answer(text)
+                .parse_mode(ParseMode::MarkdownV2)
+                .disable_web_page_preview(true);
+            let send_message = match keyboard {
+                Some(keyboard) => send_message.reply_markup(keyboard),
+                None => send_message,
+            };
+            send_message.send().await.log_on_error().await;
+        }
+        Err(err) => {
+            log::error!("Error planning trip {:?}", err);
+            context
+                .answer(catalog.list_stations_error)
+                .send()
+                .await
+                .log_on_error()
+                .await;
+        }
+    }
+    true
+}
+
+/// Pickup and dropoff station cards, with a button watching both when there
+/// are both.
+async fn trip_message(
+    storage: &Storage,
+    provider: &Provider,
+    chat_id: i64,
+    origin: &Location,
+    destination: &Location,
+    lang: Lang,
+) -> Result<(String, Option<InlineKeyboardMarkup>)> {
+    let catalog = lang.catalog();
+    let mut pickup = best_station(provider, origin, |station| {
+        station.is_renting() && station.free_bikes.unwrap_or_default() > 0
+    })
+    .await?;
+    let mut dropoff = best_station(provider, destination, |station| {
+        station.is_returning() && station.empty_slots.unwrap_or_default() > 0
+    })
+    .await?;
+    prediction::annotate(
+        storage,
+        chat_id,
+        pickup.iter_mut().chain(dropoff.iter_mut()),
+    )
+    .await;
+    let origin = geoutils::Location::new(origin.latitude, origin.longitude);
+    let destination = geoutils::Location::new(destination.latitude, destination.longitude);
+
+    let pickup_text = match &pickup {
+        Some(station) => station.message(Some(&origin), lang),
+        None => escape(catalog.trip_no_pickup),
+    };
+    let dropoff_text = match &dropoff {
+        Some(station) => station.message(Some(&destination), lang),
+        None => escape(catalog.trip_no_dropoff),
+    };
+    let text = format!(
+        "{}\n{}\n\n{}\n{}",
+        bold(&escape(catalog.trip_pickup)),
+        pickup_text,
+        bold(&escape(catalog.trip_dropoff)),
+        dropoff_text
+    );
+
+    let reminder_infos = pickup.zip(dropoff).and_then(|(pickup, dropoff)| {
+        let pickup_walk = pickup.walk_from(&origin);
+        let dropoff_walk = dropoff.walk_from(&destination);
+        let mut pickup = StationReminderInfo::try_from(pickup).ok()?;
+        let mut dropoff = StationReminderInfo::try_from(dropoff).ok()?;
+        pickup.walk = Some(pickup_walk);
+        dropoff.walk = Some(dropoff_walk);
+        Some((pickup, dropoff))
+    });
+    let keyboard = match reminder_infos {
+        Some((pickup, dropoff)) => {
+            let uuid = new_callback_uuid();
+            let button =
+                InlineKeyboardButton::callback(catalog.trip_watch_button.into(), uuid.clone());
+            let callback_data = CallbackData::StartTripReminder { pickup, dropoff };
+            save_callback_data(storage, vec![(uuid, callback_data)]).await?;
+            Some(InlineKeyboardMarkup::default().append_row(vec![button]))
+        }
+        None => None,
+    };
+    Ok((text, keyboard))
+}
+
+/// Closest open station to `location` that `usable` accepts.
+async fn best_station(
+    provider: &Provider,
+    location: &Location,
+    usable: impl Fn(&Station) -> bool,
+) -> Result<Option<Station>> {
+    let stations = find_near_stations(provider, location, TRIP_CANDIDATES, StationMode::Any)
+        .await
+        .map_err(|err| anyhow!(err))?;
+    Ok(stations
+        .into_iter()
+        .find(|station| !station.is_closed() && usable(station)))
+}
+
+async fn load_trip(storage: &Storage, chat_id: i64) -> Result<Option<TripPlan>> {
+    let trip = match storage.get_optional(&trip_key(chat_id)).await? {
+        Some(data) => Some(serde_json::from_str(&data)?),
+        None => None,
+    };
+    Ok(trip)
+}
+
+async fn save_trip(storage: &Storage, chat_id: i64, trip: &TripPlan) -> Result<()> {
+    let data = serde_json::to_string(trip)?;
+    storage
+        .set_multiple(&[(trip_key(chat_id), data)], Some(TRIP_TTL))
+        .await
+}
+
+fn trip_key(chat_id: i64) -> String {
+    format!("{}:{}", TRIP, chat_id)
+}
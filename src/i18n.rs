@@ -0,0 +1,514 @@
+use crate::store::Storage;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+const LANGUAGE: &str = "LANGUAGE";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    Pt,
+    Es,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 3] = [Lang::En, Lang::Pt, Lang::Es];
+
+    /// Matches Telegram `language_code` values such as `pt-br` or `es`.
+    pub fn from_code(code: &str) -> Option<Lang> {
+        let code = code.to_lowercase();
+        Lang::ALL
+            .iter()
+            .find(|lang| code.split(&['-', '_'][..]).next() == Some(lang.code()))
+            .copied()
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Pt => "pt",
+            Lang::Es => "es",
+        }
+    }
+
+    pub fn catalog(self) -> &'static Catalog {
+        match self {
+            Lang::En => &EN,
+            Lang::Pt => &PT,
+            Lang::Es => &ES,
+        }
+    }
+}
+
+/// Language for a chat. The one picked with `/language` wins over the
+/// Telegram user `language_code`, falling back to english.
+pub async fn language(storage: &Storage, chat_id: i64, language_code: Option<&str>) -> Lang {
+    let saved = storage
+        .get(&language_key(chat_id))
+        .await
+        .ok()
+        .and_then(|code| Lang::from_code(&code));
+    saved
+        .or_else(|| language_code.and_then(Lang::from_code))
+        .unwrap_or_default()
+}
+
+pub async fn save_language(storage: &Storage, chat_id: i64, lang: Lang) -> Result<()> {
+    storage
+        .set_multiple(&[(language_key(chat_id), lang.code().to_string())], None)
+        .await
+}
+
+fn language_key(chat_id: i64) -> String {
+    format!("{}:{}", LANGUAGE, chat_id)
+}
+
+/// Replaces `{name}` placeholders of a catalog template in a single pass, so
+/// values are never searched for placeholders themselves. Unknown ones are
+/// left as they are.
+pub fn fill(template: &str, args: &[(&str, &str)]) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let (_, value) = args.iter().find(|(name, _)| *name == &rest[1..end])?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                text.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Every user facing text. Templates marked as MarkdownV2 must keep their
+/// escaping, values passed to `fill` are escaped by the caller.
+pub struct Catalog {
+    pub language_name: &'static str,
+    pub send_location: &'static str,
+    pub send_location_button: &'static str,
+    /// MarkdownV2
+    pub about: &'static str,
+    pub list_stations_error: &'static str,
+    pub station_label: &'static str,
+    pub distance_label: &'static str,
+    pub bikes_label: &'static str,
+    pub free_slots_label: &'static str,
+    pub remind_button: &'static str,
+    pub remind_slots_button: &'static str,
+    /// `{minutes}`
+    pub reminder_created: &'static str,
+    /// `{minutes}`
+    pub slot_reminder_created: &'static str,
+    pub generic_error: &'static str,
+    pub choose_language: &'static str,
+    pub language_saved: &'static str,
+    pub save_favorite_button: &'static str,
+    pub remove_favorite_button: &'static str,
+    pub favorite_saved: &'static str,
+    pub favorite_removed: &'static str,
+    pub favorites_full: &'static str,
+    pub no_favorites: &'static str,
+    /// MarkdownV2, `{station}`
+    pub favorite_unavailable: &'static str,
+    pub stop_button: &'static str,
+    pub reminder_stopped: &'static str,
+    pub no_reminders: &'static str,
+    pub reminders_title: &'static str,
+    /// `{position}`, `{station}`, `{kind}` and `{minutes}`
+    pub reminder_line: &'static str,
+    pub kind_bikes: &'static str,
+    pub kind_slots: &'static str,
+    pub settings_title: &'static str,
+    /// `{value}` on the following settings
+    pub settings_duration: &'static str,
+    pub settings_interval: &'static str,
+    pub settings_below: &'static str,
+    pub settings_above: &'static str,
+    pub settings_off: &'static str,
+    pub settings_saved: &'static str,
+    /// MarkdownV2, `{station}` and `{amount}`
+    pub bikes_lost: &'static str,
+    /// MarkdownV2, `{station}`, `{amount}` and `{total}`
+    pub bikes_appeared: &'static str,
+    /// MarkdownV2, `{station}` and `{amount}`
+    pub slots_lost: &'static str,
+    /// MarkdownV2, `{station}`, `{amount}` and `{total}`
+    pub slots_appeared: &'static str,
+    pub inline_send_location: &'static str,
+    /// `{query}`
+    pub search_no_results: &'static str,
+    pub live_title: &'static str,
+    pub trip_origin: &'static str,
+    pub trip_destination: &'static str,
+    pub trip_pickup: &'static str,
+    pub trip_dropoff: &'static str,
+    pub trip_no_pickup: &'static str,
+    pub trip_no_dropoff: &'static str,
+    pub trip_watch_button: &'static str,
+    /// `{minutes}`
+    pub trip_reminder_created: &'static str,
+    /// `{page}`
+    pub stations_page: &'static str,
+    /// Map caption, the map isn't updated when changing page or mode
+    pub stations_map: &'static str,
+    pub previous_button: &'static str,
+    pub next_button: &'static str,
+    pub need_bike_button: &'static str,
+    pub need_slot_button: &'static str,
+    pub schedule_usage: &'static str,
+    pub schedule_created: &'static str,
+    pub no_schedules: &'static str,
+    pub schedules_title: &'static str,
+    pub schedule_line: &'static str,
+    pub delete_button: &'static str,
+    pub schedule_deleted: &'static str,
+    pub commute_alert_title: &'static str,
+    pub history_usage: &'static str,
+    pub history_title: &'static str,
+    pub history_empty: &'static str,
+    pub history_hour: &'static str,
+    pub forecast_label: &'static str,
+    pub forecast_value: &'static str,
+    pub forecast_line: &'static str,
+    pub updated_label: &'static str,
+    pub updated_minutes: &'static str,
+    pub updated_hours: &'static str,
+    pub stale_data: &'static str,
+    pub ebikes_label: &'static str,
+    pub ebikes_value: &'static str,
+    pub station_closed: &'static str,
+    pub not_renting: &'static str,
+    pub not_returning: &'static str,
+    pub remind_ebikes_button: &'static str,
+    pub ebike_reminder_created: &'static str,
+    pub ebikes_appeared: &'static str,
+    pub kind_ebikes: &'static str,
+}
+
+const EN: Catalog = Catalog {
+    language_name: "English",
+    send_location: "Send me a Location so I can send you information from near bike stations",
+    send_location_button: "Send location",
+    about: "
+Created by [Pedro Lopes](https://t.me/pdrolopes)
+Code is available on [Github](https://github.com/pdrolopes/ya_bike_bot)
+
+Information from the bike stations are fetched from [CityBikes](https://citybik.es/)\\.
+This Bot was made with [Teloxide](https://github.com/teloxide/teloxide) library
+    ",
+    list_stations_error: "There was a problem to list stations",
+    station_label: "Station",
+    distance_label: "Distance",
+    bikes_label: "Bikes",
+    free_slots_label: "Free slot",
+    remind_button: "Remind!",
+    remind_slots_button: "Remind me about slots",
+    reminder_created:
+        "I will warn you if this station has any changes in the next {minutes} minutes",
+    slot_reminder_created: "I will warn you if free slots change in the next {minutes} minutes",
+    generic_error: "There was a problem. :(",
+    choose_language: "Choose your language",
+    language_saved: "Language saved",
+    save_favorite_button: "★ Save",
+    remove_favorite_button: "✖ Remove",
+    favorite_saved: "Station saved on /favorites",
+    favorite_removed: "Station removed from favorites",
+    favorites_full: "You can keep up to 10 favorite stations",
+    no_favorites: "You have no favorite stations yet. Tap ★ Save on a station to add it.",
+    favorite_unavailable: "⚠️ Could not fetch `{station}` right now",
+    stop_button: "⏹ Stop",
+    reminder_stopped: "Reminder stopped",
+    no_reminders: "You have no active reminders",
+    reminders_title: "🔔 Active reminders",
+    reminder_line: "{position}. {station} · {kind} · {minutes} min left",
+    kind_bikes: "bikes",
+    kind_slots: "slots",
+    settings_title: "⚙️ Reminder settings",
+    settings_duration: "⏱ Watch stations for: {value}",
+    settings_interval: "🔁 Notify at most every: {value}",
+    settings_below: "⬇️ Only when below: {value}",
+    settings_above: "⬆️ Only when above: {value}",
+    settings_off: "off",
+    settings_saved: "Settings saved",
+    bikes_lost: "💔 `{station}` has lost {amount} bikes",
+    bikes_appeared: "💚 {amount} has appeard on `{station}`\\!\\!\\! It now has {total} bikes\\.",
+    slots_lost: "🅿️ `{station}` has lost {amount} free slots",
+    slots_appeared: "🅿️ {amount} free slots opened on `{station}`\\! It now has {total} slots\\.",
+    inline_send_location: "Send me your location first to search stations",
+    search_no_results: "No stations found for \"{query}\"",
+    live_title: "📍 Live location, nearest stations",
+    trip_origin: "🚩 Send me the trip origin",
+    trip_destination: "🏁 Now send the destination, you can pick it on the map with 📎 → Location",
+    trip_pickup: "🚲 Pick up at",
+    trip_dropoff: "🅿️ Drop off at",
+    trip_no_pickup: "No station with bikes near the origin",
+    trip_no_dropoff: "No station with free slots near the destination",
+    trip_watch_button: "⏰ Watch both",
+    trip_reminder_created: "Watching both stations for {minutes} minutes",
+    stations_page: "📍 Nearest stations, page {page}",
+    stations_map: "🗺 Page 1 as first listed",
+    previous_button: "⬅️ Previous",
+    next_button: "Next ➡️",
+    need_bike_button: "🚲 Need a bike",
+    need_slot_button: "🅿️ Need a slot",
+    schedule_usage: "⏰ Send /schedule <days> <HH:MM> <timezone> <station>, for example:\n/schedule mon-fri 08:30 America/Sao_Paulo Praça da Sé\nDays can be mon,wed,fri, mon-fri, weekdays, weekend or daily. The station is searched on the network of your last location.",
+    schedule_created: "⏰ I will send you the status of {station} on {days} at {time} ({timezone})",
+    no_schedules: "You have no scheduled alerts, create one with /schedule",
+    schedules_title: "⏰ Scheduled alerts",
+    schedule_line: "{position}. {station} · {days} · {time} ({timezone})",
+    delete_button: "🗑 Delete",
+    schedule_deleted: "Scheduled alert deleted",
+    commute_alert_title: "⏰ Scheduled alert",
+    history_usage: "📈 Send /history <station> to see how many bikes and slots it usually has through the day",
+    history_title: "📈 {station}, hourly averages of the last {days} days",
+    history_empty: "No history for {station} yet, it's recorded for the networks of the locations you send",
+    history_hour: "Hour (UTC)",
+    forecast_label: "Forecast",
+    forecast_value: "{in_15} in 15 min · {in_30} in 30 min",
+    forecast_line: "{trend} About {in_15} in 15 min and {in_30} in 30 min",
+    updated_label: "Updated",
+    updated_minutes: "{amount} min ago",
+    updated_hours: "{amount} h ago",
+    stale_data: "⚠️ stale data",
+    ebikes_label: "E-bikes",
+    ebikes_value: "{ebikes} electric · {normal} mechanical",
+    station_closed: "⛔ Station closed",
+    not_renting: "🚫 Not renting bikes right now",
+    not_returning: "🚫 Not accepting returns right now",
+    remind_ebikes_button: "⚡ Remind me about e-bikes",
+    ebike_reminder_created: "I will warn you if e-bikes show up in the next {minutes} minutes",
+    ebikes_appeared: "⚡ {amount} e\\-bikes available on `{station}`\\! It now has {total}\\.",
+    kind_ebikes: "e-bikes",
+};
+
+const PT: Catalog = Catalog {
+    language_name: "Português",
+    send_location: "Me envie uma localização para eu te mostrar as estações de bicicleta próximas",
+    send_location_button: "Enviar localização",
+    about: "
+Criado por [Pedro Lopes](https://t.me/pdrolopes)
+O código está disponível no [Github](https://github.com/pdrolopes/ya_bike_bot)
+
+As informações das estações vêm do [CityBikes](https://citybik.es/)\\.
+Este Bot foi feito com a biblioteca [Teloxide](https://github.com/teloxide/teloxide)
+    ",
+    list_stations_error: "Houve um problema ao listar as estações",
+    station_label: "Estação",
+    distance_label: "Distância",
+    bikes_label: "Bicicletas",
+    free_slots_label: "Vagas",
+    remind_button: "Avisar!",
+    remind_slots_button: "Avisar sobre vagas",
+    reminder_created: "Vou te avisar se essa estação mudar nos próximos {minutes} minutos",
+    slot_reminder_created: "Vou te avisar se as vagas mudarem nos próximos {minutes} minutos",
+    generic_error: "Houve um problema. :(",
+    choose_language: "Escolha seu idioma",
+    language_saved: "Idioma salvo",
+    save_favorite_button: "★ Salvar",
+    remove_favorite_button: "✖ Remover",
+    favorite_saved: "Estação salva em /favorites",
+    favorite_removed: "Estação removida dos favoritos",
+    favorites_full: "Você pode guardar até 10 estações favoritas",
+    no_favorites: "Você ainda não tem estações favoritas. Toque em ★ Salvar em uma estação.",
+    favorite_unavailable: "⚠️ Não consegui buscar `{station}` agora",
+    stop_button: "⏹ Parar",
+    reminder_stopped: "Aviso cancelado",
+    no_reminders: "Você não tem avisos ativos",
+    reminders_title: "🔔 Avisos ativos",
+    reminder_line: "{position}. {station} · {kind} · faltam {minutes} min",
+    kind_bikes: "bicicletas",
+    kind_slots: "vagas",
+    settings_title: "⚙️ Configurações dos avisos",
+    settings_duration: "⏱ Acompanhar estações por: {value}",
+    settings_interval: "🔁 Avisar no máximo a cada: {value}",
+    settings_below: "⬇️ Só quando abaixo de: {value}",
+    settings_above: "⬆️ Só quando acima de: {value}",
+    settings_off: "desligado",
+    settings_saved: "Configurações salvas",
+    bikes_lost: "💔 `{station}` perdeu {amount} bicicletas",
+    bikes_appeared:
+        "💚 {amount} apareceram em `{station}`\\!\\!\\! Agora tem {total} bicicletas\\.",
+    slots_lost: "🅿️ `{station}` perdeu {amount} vagas",
+    slots_appeared: "🅿️ {amount} vagas abriram em `{station}`\\! Agora tem {total} vagas\\.",
+    inline_send_location: "Me envie sua localização primeiro para buscar estações",
+    search_no_results: "Nenhuma estação encontrada para \"{query}\"",
+    live_title: "📍 Localização em tempo real, estações mais próximas",
+    trip_origin: "🚩 Me envie a origem da viagem",
+    trip_destination: "🏁 Agora envie o destino, você pode escolher no mapa com 📎 → Localização",
+    trip_pickup: "🚲 Retire em",
+    trip_dropoff: "🅿️ Devolva em",
+    trip_no_pickup: "Nenhuma estação com bicicletas perto da origem",
+    trip_no_dropoff: "Nenhuma estação com vagas perto do destino",
+    trip_watch_button: "⏰ Acompanhar as duas",
+    trip_reminder_created: "Acompanhando as duas estações por {minutes} minutos",
+    stations_page: "📍 Estações mais próximas, página {page}",
+    stations_map: "🗺 Página 1 como listada no início",
+    previous_button: "⬅️ Anterior",
+    next_button: "Próxima ➡️",
+    need_bike_button: "🚲 Preciso de bike",
+    need_slot_button: "🅿️ Preciso de vaga",
+    schedule_usage: "⏰ Envie /schedule <dias> <HH:MM> <fuso horário> <estação>, por exemplo:\n/schedule mon-fri 08:30 America/Sao_Paulo Praça da Sé\nOs dias podem ser mon,wed,fri, mon-fri, weekdays, weekend ou daily. A estação é buscada na rede da sua última localização.",
+    schedule_created: "⏰ Vou te enviar a situação de {station} em {days} às {time} ({timezone})",
+    no_schedules: "Você não tem alertas agendados, crie um com /schedule",
+    schedules_title: "⏰ Alertas agendados",
+    schedule_line: "{position}. {station} · {days} · {time} ({timezone})",
+    delete_button: "🗑 Apagar",
+    schedule_deleted: "Alerta agendado apagado",
+    commute_alert_title: "⏰ Alerta agendado",
+    history_usage: "📈 Envie /history <estação> para ver quantas bikes e vagas ela costuma ter ao longo do dia",
+    history_title: "📈 {station}, médias por hora dos últimos {days} dias",
+    history_empty: "Ainda não há histórico de {station}, ele é gravado para as redes das localizações que você envia",
+    history_hour: "Hora (UTC)",
+    forecast_label: "Previsão",
+    forecast_value: "{in_15} em 15 min · {in_30} em 30 min",
+    forecast_line: "{trend} Cerca de {in_15} em 15 min e {in_30} em 30 min",
+    updated_label: "Atualizado",
+    updated_minutes: "há {amount} min",
+    updated_hours: "há {amount} h",
+    stale_data: "⚠️ dados antigos",
+    ebikes_label: "Elétricas",
+    ebikes_value: "{ebikes} elétricas · {normal} mecânicas",
+    station_closed: "⛔ Estação fechada",
+    not_renting: "🚫 Sem retirada de bikes agora",
+    not_returning: "🚫 Sem devolução de bikes agora",
+    remind_ebikes_button: "⚡ Me avise de elétricas",
+    ebike_reminder_created: "Vou te avisar se aparecerem bikes elétricas nos próximos {minutes} minutos",
+    ebikes_appeared: "⚡ {amount} bikes elétricas disponíveis em `{station}`\\! Agora tem {total}\\.",
+    kind_ebikes: "elétricas",
+};
+
+const ES: Catalog = Catalog {
+    language_name: "Español",
+    send_location: "Envíame una ubicación para mostrarte las estaciones de bicicletas cercanas",
+    send_location_button: "Enviar ubicación",
+    about: "
+Creado por [Pedro Lopes](https://t.me/pdrolopes)
+El código está disponible en [Github](https://github.com/pdrolopes/ya_bike_bot)
+
+La información de las estaciones viene de [CityBikes](https://citybik.es/)\\.
+Este Bot fue hecho con la biblioteca [Teloxide](https://github.com/teloxide/teloxide)
+    ",
+    list_stations_error: "Hubo un problema al listar las estaciones",
+    station_label: "Estación",
+    distance_label: "Distancia",
+    bikes_label: "Bicicletas",
+    free_slots_label: "Anclajes",
+    remind_button: "¡Avisar!",
+    remind_slots_button: "Avisar sobre anclajes",
+    reminder_created: "Te avisaré si esta estación cambia en los próximos {minutes} minutos",
+    slot_reminder_created:
+        "Te avisaré si los anclajes libres cambian en los próximos {minutes} minutos",
+    generic_error: "Hubo un problema. :(",
+    choose_language: "Elige tu idioma",
+    language_saved: "Idioma guardado",
+    save_favorite_button: "★ Guardar",
+    remove_favorite_button: "✖ Quitar",
+    favorite_saved: "Estación guardada en /favorites",
+    favorite_removed: "Estación quitada de favoritas",
+    favorites_full: "Puedes guardar hasta 10 estaciones favoritas",
+    no_favorites: "Aún no tienes estaciones favoritas. Toca ★ Guardar en una estación.",
+    favorite_unavailable: "⚠️ No pude consultar `{station}` ahora",
+    stop_button: "⏹ Parar",
+    reminder_stopped: "Aviso cancelado",
+    no_reminders: "No tienes avisos activos",
+    reminders_title: "🔔 Avisos activos",
+    reminder_line: "{position}. {station} · {kind} · quedan {minutes} min",
+    kind_bikes: "bicicletas",
+    kind_slots: "anclajes",
+    settings_title: "⚙️ Ajustes de los avisos",
+    settings_duration: "⏱ Seguir estaciones durante: {value}",
+    settings_interval: "🔁 Avisar como máximo cada: {value}",
+    settings_below: "⬇️ Solo cuando baje de: {value}",
+    settings_above: "⬆️ Solo cuando supere: {value}",
+    settings_off: "apagado",
+    settings_saved: "Ajustes guardados",
+    bikes_lost: "💔 `{station}` perdió {amount} bicicletas",
+    bikes_appeared: "💚 ¡Aparecieron {amount} en `{station}`\\! Ahora tiene {total} bicicletas\\.",
+    slots_lost: "🅿️ `{station}` perdió {amount} anclajes libres",
+    slots_appeared:
+        "🅿️ ¡Se liberaron {amount} anclajes en `{station}`\\! Ahora tiene {total} libres\\.",
+    inline_send_location: "Envíame tu ubicación primero para buscar estaciones",
+    search_no_results: "No se encontraron estaciones para \"{query}\"",
+    live_title: "📍 Ubicación en tiempo real, estaciones más cercanas",
+    trip_origin: "🚩 Envíame el origen del viaje",
+    trip_destination: "🏁 Ahora envía el destino, puedes elegirlo en el mapa con 📎 → Ubicación",
+    trip_pickup: "🚲 Recoge en",
+    trip_dropoff: "🅿️ Deja en",
+    trip_no_pickup: "Ninguna estación con bicicletas cerca del origen",
+    trip_no_dropoff: "Ninguna estación con espacios libres cerca del destino",
+    trip_watch_button: "⏰ Seguir ambas",
+    trip_reminder_created: "Siguiendo ambas estaciones durante {minutes} minutos",
+    stations_page: "📍 Estaciones más cercanas, página {page}",
+    stations_map: "🗺 Página 1 como se listó al inicio",
+    previous_button: "⬅️ Anterior",
+    next_button: "Siguiente ➡️",
+    need_bike_button: "🚲 Necesito bici",
+    need_slot_button: "🅿️ Necesito anclaje",
+    schedule_usage: "⏰ Envía /schedule <días> <HH:MM> <zona horaria> <estación>, por ejemplo:\n/schedule mon-fri 08:30 Europe/Madrid Puerta del Sol\nLos días pueden ser mon,wed,fri, mon-fri, weekdays, weekend o daily. La estación se busca en la red de tu última ubicación.",
+    schedule_created: "⏰ Te enviaré el estado de {station} los {days} a las {time} ({timezone})",
+    no_schedules: "No tienes alertas programadas, crea una con /schedule",
+    schedules_title: "⏰ Alertas programadas",
+    schedule_line: "{position}. {station} · {days} · {time} ({timezone})",
+    delete_button: "🗑 Borrar",
+    schedule_deleted: "Alerta programada borrada",
+    commute_alert_title: "⏰ Alerta programada",
+    history_usage: "📈 Envía /history <estación> para ver cuántas bicis y anclajes suele tener a lo largo del día",
+    history_title: "📈 {station}, promedios por hora de los últimos {days} días",
+    history_empty: "Aún no hay historial de {station}, se graba para las redes de las ubicaciones que envías",
+    history_hour: "Hora (UTC)",
+    forecast_label: "Previsión",
+    forecast_value: "{in_15} en 15 min · {in_30} en 30 min",
+    forecast_line: "{trend} Unas {in_15} en 15 min y {in_30} en 30 min",
+    updated_label: "Actualizado",
+    updated_minutes: "hace {amount} min",
+    updated_hours: "hace {amount} h",
+    stale_data: "⚠️ datos antiguos",
+    ebikes_label: "Eléctricas",
+    ebikes_value: "{ebikes} eléctricas · {normal} mecánicas",
+    station_closed: "⛔ Estación cerrada",
+    not_renting: "🚫 Sin préstamo de bicis ahora",
+    not_returning: "🚫 Sin devolución de bicis ahora",
+    remind_ebikes_button: "⚡ Avísame de eléctricas",
+    ebike_reminder_created: "Te avisaré si aparecen bicis eléctricas en los próximos {minutes} minutos",
+    ebikes_appeared: "⚡ {amount} bicis eléctricas disponibles en `{station}`\\! Ahora tiene {total}\\.",
+    kind_ebikes: "eléctricas",
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_replaces_placeholders() {
+        let text = fill(
+            "{amount} bikes at {station}, {amount} total",
+            &[("station", "Rossio"), ("amount", "3")],
+        );
+        assert_eq!(text, "3 bikes at Rossio, 3 total");
+    }
+
+    #[test]
+    fn fill_keeps_placeholders_inside_values() {
+        let text = fill(
+            "No results for {query} near {station}",
+            &[("query", "{station}"), ("station", "Rossio")],
+        );
+        assert_eq!(text, "No results for {station} near Rossio");
+    }
+
+    #[test]
+    fn fill_keeps_unknown_placeholders_and_braces() {
+        assert_eq!(fill("{a} {b} { c", &[("a", "1")]), "1 {b} { c");
+    }
+}
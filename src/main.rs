@@ -1,19 +1,45 @@
+// serde_derive 1.0.105, kept for teloxide 0.2 which needs `serde::export`,
+// expands to code that current compilers lint
+#![allow(unexpected_cfgs, non_local_definitions)]
 pub mod bike_service;
+mod commute_alert;
 mod config;
 pub mod handle_callback_query;
+mod handle_favorites;
+mod handle_history;
+mod handle_inline_query;
+mod handle_live_location;
 mod handle_location;
+mod handle_reminders;
+mod handle_schedule;
+mod handle_search;
+mod handle_settings;
+mod handle_trip;
+mod i18n;
 pub mod models;
+mod prediction;
 pub mod redis_helper;
+mod search;
+mod station_history;
 pub mod station_low_warn;
+mod station_map;
+mod store;
 mod web_hooks;
+use bike_service::Provider;
 use config::Config;
+use handle_callback_query::{new_callback_uuid, save_callback_data};
 use handle_location::handle as handle_location;
+use i18n::Lang;
+use models::CallbackData;
 use std::sync::Arc;
 use std::time::Duration;
+const STATION_HISTORY_INTERVAL: u64 = 60 * 10; // 10 minutes
+use store::Storage;
 use teloxide::prelude::*;
 use teloxide::requests::SendChatActionKind;
 use teloxide::types::{
-    ButtonRequest, CallbackQuery, KeyboardButton, ParseMode, ReplyKeyboardMarkup,
+    ButtonRequest, CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, InlineQuery,
+    KeyboardButton, ParseMode, ReplyKeyboardMarkup,
 };
 
 #[tokio::main]
@@ -27,48 +53,49 @@ async fn run() {
     log::info!("Starting Yet Another Bike Bot");
 
     let config = Config::new();
+    let storage = store::connect(&config).expect("Invalid DATABASE_URL");
+    let provider = bike_service::new_provider(config.gbfs_feeds);
     let bot = Bot::new(config.telegram_token);
-    start_station_warn_loop(bot.clone());
+    start_station_warn_loop(storage.clone(), provider.clone(), bot.clone());
+    start_commute_alert_loop(storage.clone(), provider.clone(), bot.clone());
+    start_station_history_loop(storage.clone(), provider.clone());
 
+    let (messages_storage, messages_provider) = (storage.clone(), provider.clone());
+    let (callbacks_storage, callbacks_provider) = (storage.clone(), provider.clone());
+    let (edits_storage, edits_provider) = (storage.clone(), provider.clone());
     let dispatcher = Dispatcher::new(bot.clone())
-        .messages_handler(|rx: DispatcherHandlerRx<Message>| {
-            rx.for_each_concurrent(None, |context| async move {
-                let DispatcherHandlerCx { update, bot } = &context;
-
-                //Send action that shows "Typing..."
-                let send_action =
-                    bot.send_chat_action(update.chat_id(), SendChatActionKind::Typing);
-                tokio::spawn(async move { send_action.send().await });
-
-                // Log user
-                if let Some(user) = update.from() {
+        .messages_handler(move |rx: DispatcherHandlerRx<Message>| {
+            rx.for_each_concurrent(None, move |context| {
+                let storage = messages_storage.clone();
+                let provider = messages_provider.clone();
+                async move { handle_message(context, &storage, &provider).await }
+            })
+        })
+        .edited_messages_handler(move |rx: DispatcherHandlerRx<Message>| {
+            rx.for_each_concurrent(None, move |context| {
+                let storage = edits_storage.clone();
+                let provider = edits_provider.clone();
+                async move { handle_live_location::handle(&context, &storage, &provider).await }
+            })
+        })
+        .callback_queries_handler(move |rx: DispatcherHandlerRx<CallbackQuery>| {
+            rx.for_each_concurrent(None, move |context| {
+                let storage = callbacks_storage.clone();
+                let provider = callbacks_provider.clone();
+                async move {
+                    let user = &context.update.from;
                     let mention = user.mention().unwrap_or_default();
-                    log::info!("Message from: {}, {} ", user.full_name(), mention);
-                };
-
-                // Handle commands
-                let message_text = update.text_owned().unwrap_or_default();
-                let message_location = update.location();
-                if message_text.starts_with("/start") {
-                    handle_start(&context).await;
-                    return;
-                } else if message_text.starts_with("/about") {
-                    handle_about(&context).await;
-                    return;
-                } else if message_location.is_some() {
-                    handle_location(&context).await;
-                } else {
-                    handle_start(&context).await;
+                    log::info!("Callback query from: {}, {} ", user.full_name(), mention);
+
+                    handle_callback_query::handle(&context, &storage, &provider).await;
                 }
             })
         })
-        .callback_queries_handler(|rx: DispatcherHandlerRx<CallbackQuery>| {
-            rx.for_each_concurrent(None, |context| async move {
-                let user = &context.update.from;
-                let mention = user.mention().unwrap_or_default();
-                log::info!("Callback query from: {}, {} ", user.full_name(), mention);
-
-                handle_callback_query::handle(&context).await;
+        .inline_queries_handler(move |rx: DispatcherHandlerRx<InlineQuery>| {
+            rx.for_each_concurrent(None, move |context| {
+                let storage = storage.clone();
+                let provider = provider.clone();
+                async move { handle_inline_query::handle(&context, &storage, &provider).await }
             })
         });
     if config.poll {
@@ -83,13 +110,69 @@ async fn run() {
     };
 }
 
-async fn handle_start(context: &DispatcherHandlerCx<Message>) {
-    let location_button = KeyboardButton::new("Send location").request(ButtonRequest::Location);
+async fn handle_message(
+    context: DispatcherHandlerCx<Message>,
+    storage: &Storage,
+    provider: &Provider,
+) {
+    let DispatcherHandlerCx { update, bot } = &context;
+
+    //Send action that shows "Typing..."
+    let send_action = bot.send_chat_action(update.chat_id(), SendChatActionKind::Typing);
+    tokio::spawn(async move { send_action.send().await });
+
+    // Log user
+    if let Some(user) = update.from() {
+        let mention = user.mention().unwrap_or_default();
+        log::info!("Message from: {}, {} ", user.full_name(), mention);
+    };
+
+    let language_code = update.from().and_then(|user| user.language_code.as_deref());
+    let lang = i18n::language(storage, update.chat_id(), language_code).await;
+
+    // Handle commands
+    let message_text = update.text_owned().unwrap_or_default();
+    let message_location = update.location();
+    if message_text.starts_with("/start") {
+        handle_start(&context, lang).await;
+    } else if message_text.starts_with("/about") {
+        handle_about(&context, lang).await;
+    } else if message_text.starts_with("/favorites") {
+        handle_favorites::handle(&context, storage, provider, lang).await;
+    } else if message_text.starts_with("/reminders") {
+        handle_reminders::handle(&context, storage, lang).await;
+    } else if message_text.starts_with("/settings") {
+        handle_settings::handle(&context, storage, lang).await;
+    } else if message_text.starts_with("/language") {
+        handle_language(&context, storage, lang).await;
+    } else if message_text.starts_with("/schedules") {
+        handle_schedule::handle_list(&context, storage, lang).await;
+    } else if message_text.starts_with("/schedule") {
+        handle_schedule::handle(&context, storage, provider, lang).await;
+    } else if message_text.starts_with("/history") {
+        handle_history::handle(&context, storage, provider, lang).await;
+    } else if message_text.starts_with("/trip") {
+        handle_trip::handle(&context, storage, lang).await;
+    } else if message_location.is_some() {
+        if !handle_trip::handle_location(&context, storage, provider, lang).await {
+            handle_location(&context, storage, provider, lang).await;
+        }
+    } else if !message_text.is_empty() && !message_text.starts_with('/') {
+        handle_search::handle(&context, storage, provider, lang).await;
+    } else {
+        handle_start(&context, lang).await;
+    }
+}
+
+async fn handle_start(context: &DispatcherHandlerCx<Message>, lang: Lang) {
+    let catalog = lang.catalog();
+    let location_button =
+        KeyboardButton::new(catalog.send_location_button).request(ButtonRequest::Location);
     let keyboard = ReplyKeyboardMarkup::default()
         .resize_keyboard(true)
         .append_row(vec![location_button]);
     context
-        .answer("Send me a Location so I can send you information from near bike stations")
+        .answer(catalog.send_location)
         .reply_markup(keyboard)
         .send()
         .await
@@ -97,16 +180,9 @@ async fn handle_start(context: &DispatcherHandlerCx<Message>) {
         .await;
 }
 
-async fn handle_about(context: &DispatcherHandlerCx<Message>) {
-    let message = "
-Created by [Pedro Lopes](https://t.me/pdrolopes)
-Code is available on [Github](https://github.com/pdrolopes/ya_bike_bot)
-
-Information from the bike stations are fetched from [CityBikes](https://citybik.es/)\\.
-This Bot was made with [Teloxide](https://github.com/teloxide/teloxide) library
-    ";
+async fn handle_about(context: &DispatcherHandlerCx<Message>, lang: Lang) {
     context
-        .answer(message)
+        .answer(lang.catalog().about)
         .parse_mode(ParseMode::MarkdownV2)
         .disable_web_page_preview(true)
         .send()
@@ -115,14 +191,36 @@ This Bot was made with [Teloxide](https://github.com/teloxide/teloxide) library
         .await;
 }
 
+async fn handle_language(context: &DispatcherHandlerCx<Message>, storage: &Storage, lang: Lang) {
+    let (buttons, callback_data): (Vec<_>, Vec<_>) = Lang::ALL
+        .iter()
+        .map(|&option| {
+            let uuid = new_callback_uuid();
+            let button =
+                InlineKeyboardButton::callback(option.catalog().language_name.into(), uuid.clone());
+            (button, (uuid, CallbackData::SetLanguage(option)))
+        })
+        .unzip();
+    if let Err(err) = save_callback_data(storage, callback_data).await {
+        log::error!("Error saving language callback data {:?}", err);
+        return;
+    }
+    context
+        .answer(lang.catalog().choose_language)
+        .reply_markup(InlineKeyboardMarkup::default().append_row(buttons))
+        .send()
+        .await
+        .log_on_error()
+        .await;
+}
+
 // TODO name this better
-fn start_station_warn_loop(bot: Arc<Bot>) {
+fn start_station_warn_loop(storage: Storage, provider: Provider, bot: Arc<Bot>) {
     log::info!("Started loop");
     tokio::spawn(async move {
         loop {
             let bot = bot.clone();
-            // TODO Moved redis to a centrlized place.
-            station_low_warn::check_active_warn_stations(bot)
+            station_low_warn::check_active_warn_stations(&storage, &provider, bot)
                 .await
                 .unwrap_or_else(|err| {
                     log::error!("While checking active station warns. {:?}", err)
@@ -131,3 +229,25 @@ fn start_station_warn_loop(bot: Arc<Bot>) {
         }
     });
 }
+
+fn start_commute_alert_loop(storage: Storage, provider: Provider, bot: Arc<Bot>) {
+    tokio::spawn(async move {
+        loop {
+            commute_alert::check_commute_alerts(&storage, &provider, bot.clone())
+                .await
+                .unwrap_or_else(|err| log::error!("While checking commute alerts. {:?}", err));
+            tokio::time::delay_for(Duration::new(60, 0)).await
+        }
+    });
+}
+
+fn start_station_history_loop(storage: Storage, provider: Provider) {
+    tokio::spawn(async move {
+        loop {
+            station_history::collect_station_history(&storage, &provider)
+                .await
+                .unwrap_or_else(|err| log::error!("While recording station history. {:?}", err));
+            tokio::time::delay_for(Duration::new(STATION_HISTORY_INTERVAL, 0)).await
+        }
+    });
+}
@@ -1,9 +1,11 @@
-use crate::bike_service::Station;
+use crate::bike_service::{Station, Walk};
+use crate::i18n::Lang;
 use anyhow::{anyhow, Result};
 use chrono::prelude::*;
 use derive_more::From;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use teloxide::types::Location;
 use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -14,38 +16,220 @@ pub struct StationWarn {
     pub updated_at: DateTime<Utc>,
     pub chat_id: i64,
     pub station_info: StationReminderInfo,
+    #[serde(default)]
+    pub lang: Lang,
+    #[serde(default)]
+    pub kind: WarnKind,
+    #[serde(default)]
+    pub settings: WarnSettings,
+    /// Callback data uuid of the "Stop" button sent with every notification.
+    #[serde(default)]
+    pub stop_callback: Option<String>,
+}
+
+/// Per chat reminder preferences, copied into each `StationWarn` when created.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct WarnSettings {
+    pub duration_minutes: u32,
+    pub interval_minutes: u32,
+    /// Only notify when the watched amount drops below this value.
+    pub below: Option<u32>,
+    /// Only notify when the watched amount rises above this value.
+    pub above: Option<u32>,
+}
+
+impl Default for WarnSettings {
+    fn default() -> Self {
+        WarnSettings {
+            duration_minutes: 30,
+            interval_minutes: 5,
+            below: None,
+            above: None,
+        }
+    }
+}
+
+/// What a station warn watches.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum WarnKind {
+    #[default]
+    Bikes,
+    Slots,
+    /// Only notified when electric bikes show up
+    EBikes,
 }
 
 #[derive(Serialize, Deserialize, Debug, From)]
 pub enum CallbackData {
     StartStationReminder(StationReminderInfo),
+    #[from(ignore)]
+    StartSlotReminder(StationReminderInfo),
+    #[from(ignore)]
+    StartEBikeReminder(StationReminderInfo),
+    SetLanguage(Lang),
+    UpdateSettings(WarnSettings),
+    #[from(ignore)]
+    StopStationReminder {
+        warn_uuid: String,
+        refresh_list: bool,
+    },
+    #[from(ignore)]
+    SaveFavorite(FavoriteStation),
+    #[from(ignore)]
+    RemoveFavorite(FavoriteStation),
+    #[from(ignore)]
+    StartTripReminder {
+        pickup: StationReminderInfo,
+        dropoff: StationReminderInfo,
+    },
+    #[from(ignore)]
+    StopTripReminder {
+        warn_uuids: Vec<String>,
+    },
+    #[from(ignore)]
+    StationsPage {
+        location: Location,
+        page: usize,
+    },
+    #[from(ignore)]
+    SetStationMode {
+        location: Location,
+        mode: StationMode,
+    },
+    #[from(ignore)]
+    DeleteCommuteAlert {
+        alert_uuid: String,
+    },
+}
+
+/// Which stations a chat is after, stations with none of it are skipped.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum StationMode {
+    #[default]
+    Any,
+    NeedBike,
+    NeedSlot,
+}
+
+impl StationMode {
+    pub fn accepts(self, station: &Station) -> bool {
+        match self {
+            StationMode::Any => true,
+            StationMode::NeedBike => station.free_bikes != Some(0),
+            StationMode::NeedSlot => station.empty_slots != Some(0),
+        }
+    }
+}
+
+impl CallbackData {
+    pub fn station_reminder(kind: WarnKind, station_info: StationReminderInfo) -> Self {
+        match kind {
+            WarnKind::Bikes => CallbackData::StartStationReminder(station_info),
+            WarnKind::Slots => CallbackData::StartSlotReminder(station_info),
+            WarnKind::EBikes => CallbackData::StartEBikeReminder(station_info),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FavoriteStation {
+    pub id: String,
+    pub network_href: String,
+    pub name: String,
+}
+
+/// A location message that may be a live location. Once Telegram sends its
+/// first update, a single message with the nearest stations is kept edited.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct LiveLocation {
+    pub results_message_id: Option<i32>,
+    pub refreshed_at: Option<DateTime<Utc>>,
+    pub text: String,
+}
+
+/// Recurring `/schedule` alert, sending the station status on `weekdays` at
+/// `time` in `timezone`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommuteAlert {
+    pub uuid: String,
+    pub chat_id: i64,
+    pub station_info: StationReminderInfo,
+    pub weekdays: Vec<Weekday>,
+    pub time: NaiveTime,
+    /// IANA name, as in `America/Sao_Paulo`
+    pub timezone: String,
+    #[serde(default)]
+    pub lang: Lang,
+}
+
+/// `/trip` conversation, waiting for the origin and then the destination.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TripPlan {
+    pub origin: Option<Location>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StationReminderInfo {
     pub uuid: String,
+    #[serde(default)]
+    pub name: String,
     pub network_href: String,
     pub free_bikes: u32,
+    #[serde(default)]
+    pub empty_slots: u32,
     pub id: String,
+    #[serde(default)]
+    pub ebikes: u32,
+    /// When the station last reported, as of the last check.
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// From where the reminder was asked for, when known.
+    #[serde(default)]
+    pub walk: Option<Walk>,
 }
 
 impl TryFrom<Station> for StationReminderInfo {
     type Error = anyhow::Error;
 
     fn try_from(station: Station) -> Result<Self> {
+        let ebikes = station.ebikes().unwrap_or_default();
         let network_href = station
             .network_href
             .ok_or_else(|| anyhow!("missing network_href"))?;
         let free_bikes = station
             .free_bikes
             .ok_or_else(|| anyhow!("missing free bikes"))?;
+        let empty_slots = station
+            .empty_slots
+            .ok_or_else(|| anyhow!("missing empty slots"))?;
         let id = station.id;
         let uuid = Uuid::new_v4().to_simple().to_string();
         Ok(StationReminderInfo {
             uuid,
+            name: station.name,
             network_href,
             free_bikes,
+            empty_slots,
             id,
+            ebikes,
+            timestamp: Some(station.timestamp),
+            walk: None,
+        })
+    }
+}
+
+impl TryFrom<&Station> for FavoriteStation {
+    type Error = anyhow::Error;
+
+    fn try_from(station: &Station) -> Result<Self> {
+        let network_href = station
+            .network_href
+            .clone()
+            .ok_or_else(|| anyhow!("missing network_href"))?;
+        Ok(FavoriteStation {
+            id: station.id.clone(),
+            network_href,
+            name: station.name.clone(),
         })
     }
 }
@@ -0,0 +1,352 @@
+use crate::bike_service::Station;
+use crate::commute_alert::chat_commute_alerts;
+use crate::station_history::{station_history, Sample};
+use crate::store::Storage;
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use futures::future::join_all;
+const DAY: i64 = 60 * 60 * 24;
+/// Previous days compared, back to the same weekday last week
+const PATTERN_DAYS: i64 = 7;
+/// Samples this recent give the current trend
+const RECENT_WINDOW: i64 = 60 * 45; // 45 minutes
+/// How far a past sample can be from the time it stands for
+const SAMPLE_TOLERANCE: i64 = 60 * 10; // 10 minutes
+const HORIZON_15: i64 = 60 * 15;
+const HORIZON_30: i64 = 60 * 30;
+
+/// Estimated amounts in 15 and 30 minutes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Forecast {
+    pub in_15: u32,
+    pub in_30: u32,
+}
+
+impl Forecast {
+    /// Arrow comparing the amount in 30 minutes to `current`.
+    pub fn trend(&self, current: u32) -> &'static str {
+        match self.in_30 {
+            in_30 if in_30 > current => "↗",
+            in_30 if in_30 < current => "↘",
+            _ => "→",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prediction {
+    pub free_bikes: Forecast,
+    pub empty_slots: Forecast,
+}
+
+/// Fills the prediction of each station that has some history recorded.
+/// Days are told apart in the network timezone, or else in the one `chat_id`
+/// set its `/schedule` alerts in.
+pub async fn annotate<'a>(
+    storage: &Storage,
+    chat_id: i64,
+    stations: impl IntoIterator<Item = &'a mut Station>,
+) {
+    let mut stations: Vec<&mut Station> = stations.into_iter().collect();
+    let chat_tz = if stations.iter().all(|station| station.tz().is_some()) {
+        None
+    } else {
+        chat_timezone(storage, chat_id).await
+    };
+    let histories = join_all(
+        stations
+            .iter()
+            .map(|station| station_history(storage, station)),
+    )
+    .await;
+    let now = Utc::now().timestamp();
+    for (station, history) in stations.iter_mut().zip(histories) {
+        match history {
+            Ok(samples) => {
+                let tz = station.tz().or(chat_tz).unwrap_or(Tz::UTC);
+                station.prediction = predict(station, &samples, now, tz)
+            }
+            Err(err) => log::error!("Error loading station history {:?}", err),
+        }
+    }
+}
+
+async fn chat_timezone(storage: &Storage, chat_id: i64) -> Option<Tz> {
+    match chat_commute_alerts(storage, chat_id).await {
+        Ok(alerts) => alerts.first().map(|alert| alert.tz()),
+        Err(err) => {
+            log::error!("Error loading chat timezone {:?}", err);
+            None
+        }
+    }
+}
+
+/// Blends the recent trend with how the station changed around the same time
+/// on previous days, preferring days of the same kind (weekday or weekend) in
+/// `tz`.
+pub fn predict(station: &Station, samples: &[Sample], now: i64, tz: Tz) -> Option<Prediction> {
+    if samples.len() < 2 {
+        return None;
+    }
+    let free_bikes = station.free_bikes?;
+    let empty_slots = station.empty_slots?;
+    let capacity = free_bikes + empty_slots;
+    let forecast = |current: u32, amount: fn(&Sample) -> u32| {
+        let slope = recent_slope(samples, now, amount);
+        let estimate = |horizon: i64| {
+            let pattern = pattern_delta(samples, now, horizon, tz, amount);
+            let delta = match (slope, pattern) {
+                (Some(slope), Some(pattern)) => (slope * horizon as f32 + pattern) / 2.0,
+                (Some(slope), None) => slope * horizon as f32,
+                (None, Some(pattern)) => pattern,
+                (None, None) => 0.0,
+            };
+            (current as f32 + delta)
+                .round()
+                .max(0.0)
+                .min(capacity as f32) as u32
+        };
+        Forecast {
+            in_15: estimate(HORIZON_15),
+            in_30: estimate(HORIZON_30),
+        }
+    };
+    Some(Prediction {
+        free_bikes: forecast(free_bikes, |sample| sample.free_bikes),
+        empty_slots: forecast(empty_slots, |sample| sample.empty_slots),
+    })
+}
+
+/// Least squares change per second over the `RECENT_WINDOW` samples.
+fn recent_slope(samples: &[Sample], now: i64, amount: fn(&Sample) -> u32) -> Option<f32> {
+    let recent: Vec<(f32, f32)> = samples
+        .iter()
+        .filter(|sample| now - sample.at <= RECENT_WINDOW)
+        .map(|sample| ((sample.at - now) as f32, amount(sample) as f32))
+        .collect();
+    if recent.len() < 2 {
+        return None;
+    }
+    let count = recent.len() as f32;
+    let mean_x = recent.iter().map(|(x, _)| x).sum::<f32>() / count;
+    let mean_y = recent.iter().map(|(_, y)| y).sum::<f32>() / count;
+    let covariance: f32 = recent
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f32 = recent.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if variance == 0.0 {
+        return None;
+    }
+    Some(covariance / variance)
+}
+
+/// Average change from `now` to `now + horizon` on the previous days, days
+/// told apart in `tz`.
+fn pattern_delta(
+    samples: &[Sample],
+    now: i64,
+    horizon: i64,
+    tz: Tz,
+    amount: fn(&Sample) -> u32,
+) -> Option<f32> {
+    let is_weekend = |timestamp: i64| {
+        let weekday = tz.timestamp(timestamp, 0).weekday();
+        weekday == Weekday::Sat || weekday == Weekday::Sun
+    };
+    let (same_kind, other_kind): (Vec<_>, Vec<_>) = (1..=PATTERN_DAYS)
+        .map(|days| now - days * DAY)
+        .filter_map(|start| {
+            let from = closest(samples, start)?;
+            let to = closest(samples, start + horizon)?;
+            Some((start, amount(to) as f32 - amount(from) as f32))
+        })
+        .partition(|(start, _)| is_weekend(*start) == is_weekend(now));
+    let deltas = if same_kind.is_empty() {
+        other_kind
+    } else {
+        same_kind
+    };
+    if deltas.is_empty() {
+        return None;
+    }
+    Some(deltas.iter().map(|(_, delta)| delta).sum::<f32>() / deltas.len() as f32)
+}
+
+fn closest(samples: &[Sample], at: i64) -> Option<&Sample> {
+    samples
+        .iter()
+        .filter(|sample| (sample.at - at).abs() <= SAMPLE_TOLERANCE)
+        .min_by_key(|sample| (sample.at - at).abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monday, 2021-06-07 12:00 UTC
+    const NOW: i64 = 1_623_067_200;
+    const MINUTE: i64 = 60;
+
+    fn station(free_bikes: u32, empty_slots: u32) -> Station {
+        Station {
+            free_bikes: Some(free_bikes),
+            empty_slots: Some(empty_slots),
+            id: "station".to_string(),
+            latitude: 38.72,
+            longitude: -9.14,
+            name: "Rossio".to_string(),
+            timestamp: Utc.timestamp(NOW, 0),
+            extra: None,
+            network_href: None,
+            timezone: None,
+            prediction: None,
+        }
+    }
+
+    fn sample(at: i64, free_bikes: u32, capacity: u32) -> Sample {
+        Sample {
+            at,
+            free_bikes,
+            empty_slots: capacity - free_bikes,
+        }
+    }
+
+    /// Samples every 5 minutes over the last 45, from `first` bikes changing
+    /// by `step` each time.
+    fn recent(first: i64, step: i64, capacity: u32) -> Vec<Sample> {
+        (0..10)
+            .map(|index| {
+                let at = NOW - 45 * MINUTE + index * 5 * MINUTE;
+                sample(at, (first + index * step) as u32, capacity)
+            })
+            .collect()
+    }
+
+    fn bikes(sample: &Sample) -> u32 {
+        sample.free_bikes
+    }
+
+    #[test]
+    fn finds_the_closest_sample_within_tolerance() {
+        let samples = vec![
+            sample(NOW - 8 * MINUTE, 1, 10),
+            sample(NOW + 3 * MINUTE, 2, 10),
+        ];
+        assert_eq!(closest(&samples, NOW).unwrap().free_bikes, 2);
+        assert_eq!(closest(&samples, NOW - 15 * MINUTE).unwrap().free_bikes, 1);
+        assert!(closest(&samples, NOW + 20 * MINUTE).is_none());
+    }
+
+    #[test]
+    fn measures_the_recent_slope() {
+        assert_eq!(recent_slope(&recent(5, 0, 10), NOW, bikes), Some(0.0));
+        let rising = recent_slope(&recent(0, 1, 10), NOW, bikes).unwrap();
+        assert!((rising - 1.0 / (5 * MINUTE) as f32).abs() < 1e-6);
+        let emptying = recent_slope(&recent(9, -1, 10), NOW, bikes).unwrap();
+        assert!((emptying + 1.0 / (5 * MINUTE) as f32).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ignores_old_and_single_samples() {
+        let old = vec![sample(NOW - DAY, 1, 10), sample(NOW - DAY + MINUTE, 5, 10)];
+        assert_eq!(recent_slope(&old, NOW, bikes), None);
+        assert_eq!(recent_slope(&old[..1], NOW, bikes), None);
+    }
+
+    #[test]
+    fn keeps_a_flat_station_as_is() {
+        let prediction = predict(&station(5, 15), &recent(5, 0, 20), NOW, Tz::UTC).unwrap();
+        assert_eq!(prediction.free_bikes, Forecast { in_15: 5, in_30: 5 });
+        assert_eq!(
+            prediction.empty_slots,
+            Forecast {
+                in_15: 15,
+                in_30: 15
+            }
+        );
+    }
+
+    #[test]
+    fn follows_a_rising_station() {
+        let prediction = predict(&station(9, 11), &recent(0, 1, 20), NOW, Tz::UTC).unwrap();
+        assert_eq!(
+            prediction.free_bikes,
+            Forecast {
+                in_15: 12,
+                in_30: 15
+            }
+        );
+        assert_eq!(prediction.empty_slots, Forecast { in_15: 8, in_30: 5 });
+    }
+
+    #[test]
+    fn clamps_to_the_station_capacity() {
+        // Losing 2 bikes every 5 minutes
+        let prediction = predict(&station(2, 18), &recent(20, -2, 20), NOW, Tz::UTC).unwrap();
+        assert_eq!(prediction.free_bikes, Forecast { in_15: 0, in_30: 0 });
+        assert_eq!(
+            prediction.empty_slots,
+            Forecast {
+                in_15: 20,
+                in_30: 20
+            }
+        );
+    }
+
+    #[test]
+    fn needs_some_history() {
+        assert_eq!(predict(&station(5, 5), &[], NOW, Tz::UTC), None);
+        assert_eq!(
+            predict(&station(5, 5), &recent(5, 0, 10)[..1], NOW, Tz::UTC),
+            None
+        );
+    }
+
+    #[test]
+    fn repeats_the_same_weekday_last_week() {
+        let samples = vec![
+            sample(NOW - 7 * DAY, 10, 20),
+            sample(NOW - 7 * DAY + HORIZON_30, 4, 20),
+        ];
+        let prediction = predict(&station(10, 10), &samples, NOW, Tz::UTC).unwrap();
+        assert_eq!(prediction.free_bikes.in_30, 4);
+    }
+
+    #[test]
+    fn tells_weekends_apart_in_local_time() {
+        // Sunday 21:00 in Los Angeles, while already Monday in UTC
+        let now = NOW - 8 * 60 * MINUTE;
+        let tz = chrono_tz::America::Los_Angeles;
+        let samples = vec![
+            // Saturday evening, a weekend day both locally and in UTC
+            sample(now - DAY, 4, 20),
+            sample(now - DAY + HORIZON_30, 8, 20),
+            // Friday evening, already Saturday in UTC
+            sample(now - 2 * DAY, 8, 20),
+            sample(now - 2 * DAY + HORIZON_30, 4, 20),
+        ];
+        assert_eq!(
+            pattern_delta(&samples, now, HORIZON_30, tz, bikes),
+            Some(4.0)
+        );
+    }
+
+    #[test]
+    fn tells_weekends_apart_in_summer_time() {
+        // Monday 00:30 in Madrid, summer time, while still Sunday in UTC
+        let now = NOW - 12 * 60 * MINUTE - 90 * MINUTE;
+        let samples = vec![
+            // Sunday, a weekend day
+            sample(now - DAY, 4, 20),
+            sample(now - DAY + HORIZON_30, 8, 20),
+            // Monday last week
+            sample(now - 7 * DAY, 8, 20),
+            sample(now - 7 * DAY + HORIZON_30, 4, 20),
+        ];
+        assert_eq!(
+            pattern_delta(&samples, now, HORIZON_30, chrono_tz::Europe::Madrid, bikes),
+            Some(-4.0)
+        );
+    }
+}
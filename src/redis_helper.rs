@@ -1,57 +1,284 @@
-use crate::config::Config;
-use redis::aio::Connection;
-use redis::AsyncCommands;
-use redis::RedisResult;
+use crate::store::{Batch, Condition, Store, Write};
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::lock::Mutex as AsyncMutex;
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, RedisError, RedisResult};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-// TODO use connection pool
+/// `Store::apply` with a condition. KEYS: the condition key, then the key of
+/// every write. ARGV: the condition kind and size, then an operation and two
+/// arguments per write.
+const CONDITIONAL_BATCH_SCRIPT: &str = r"
+local exists = redis.call('EXISTS', KEYS[1]) == 1
+if ARGV[1] == 'exists' and not exists
+    or ARGV[1] == 'absent' and exists
+    or ARGV[1] == 'smaller' and redis.call('SCARD', KEYS[1]) >= tonumber(ARGV[2]) then
+    return 0
+end
+for i = 2, #KEYS do
+    local key, op, a, b = KEYS[i], ARGV[i * 3 - 3], ARGV[i * 3 - 2], ARGV[i * 3 - 1]
+    if op == 'set' then
+        redis.call('SET', key, a)
+        if tonumber(b) > 0 then
+            redis.call('EXPIRE', key, b)
+        end
+    elseif op == 'del' then
+        redis.call('DEL', key)
+    elseif op == 'sadd' then
+        redis.call('SADD', key, a)
+    elseif op == 'srem' then
+        redis.call('SREM', key, a)
+    elseif op == 'zadd' then
+        redis.call('ZADD', key, a, b)
+    elseif op == 'zrem' then
+        redis.call('ZREM', key, a)
+    elseif op == 'zremrangebyscore' then
+        redis.call('ZREMRANGEBYSCORE', key, '-inf', a)
+    end
+end
+return 1
+";
 
-async fn get_connection() -> RedisResult<Connection> {
-    let client = redis::Client::open(Config::new().redis_url)?;
-    client.get_async_connection().await
+/// Redis backed `Store`. Keeps `pool_size` multiplexed connections, handed out round robin,
+/// opened on first use and reopened after a connection failure.
+#[derive(Clone)]
+pub struct RedisStore {
+    pool: Arc<Pool>,
 }
 
-pub async fn keys(pattern: Option<&str>) -> RedisResult<Vec<String>> {
-    let pattern = pattern.unwrap_or_default();
-    let mut connection = get_connection().await?;
-    let data = connection.keys(pattern).await?;
-    Ok(data)
+struct Pool {
+    client: redis::Client,
+    connections: Vec<AsyncMutex<Option<MultiplexedConnection>>>,
+    next: AtomicUsize,
 }
 
-pub async fn get(key: &str) -> RedisResult<String> {
-    let mut connection = get_connection().await?;
-    let data = connection.get(key).await?;
-    Ok(data)
+impl RedisStore {
+    pub fn new(redis_url: &str, pool_size: usize) -> RedisResult<RedisStore> {
+        let client = redis::Client::open(redis_url)?;
+        let connections = (0..pool_size.max(1))
+            .map(|_| AsyncMutex::new(None))
+            .collect();
+        Ok(RedisStore {
+            pool: Arc::new(Pool {
+                client,
+                connections,
+                next: AtomicUsize::new(0),
+            }),
+        })
+    }
+
+    async fn connection(&self, slot: usize) -> RedisResult<MultiplexedConnection> {
+        let mut connection = self.pool.connections[slot].lock().await;
+        if let Some(connection) = connection.as_ref() {
+            return Ok(connection.clone());
+        }
+        let opened = self.pool.client.get_multiplexed_tokio_connection().await?;
+        *connection = Some(opened.clone());
+        Ok(opened)
+    }
+
+    /// Runs `command` on the next pooled connection. If the connection is
+    /// broken it is dropped and the command retried once on a new one.
+    async fn run<T, F, Fut>(&self, command: F) -> RedisResult<T>
+    where
+        F: Fn(MultiplexedConnection) -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        let slot = self.pool.next.fetch_add(1, Ordering::Relaxed) % self.pool.connections.len();
+        match command(self.connection(slot).await?).await {
+            Err(err) if is_connection_error(&err) => {
+                log::warn!("Redis connection lost, reconnecting. {:?}", err);
+                *self.pool.connections[slot].lock().await = None;
+                command(self.connection(slot).await?).await
+            }
+            result => result,
+        }
+    }
+
+    async fn query_atomic<T: redis::FromRedisValue>(
+        &self,
+        mut pipeline: redis::Pipeline,
+    ) -> RedisResult<T> {
+        pipeline.atomic();
+        let pipeline = &pipeline;
+        self.run(|mut connection| async move { pipeline.query_async(&mut connection).await })
+            .await
+    }
 }
 
-pub async fn set_multiple(tuples: &[(String, String)], expire: Option<usize>) -> RedisResult<()> {
-    let mut connection = get_connection().await?;
-    let mut pipeline = redis::Pipeline::new();
-    tuples.iter().for_each(|(key, value)| {
-        pipeline.set(key, value).ignore();
-        if let Some(expire) = expire {
-            pipeline.expire(key, expire);
+#[async_trait]
+impl Store for RedisStore {
+    async fn keys(&self, pattern: Option<&str>) -> Result<Vec<String>> {
+        let pattern = pattern.unwrap_or_default();
+        Ok(self
+            .run(|mut connection| async move { connection.keys(pattern).await })
+            .await?)
+    }
+
+    async fn get_optional(&self, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .run(|mut connection| async move { connection.get(key).await })
+            .await?)
+    }
+
+    async fn set_multiple(&self, tuples: &[(String, String)], expire: Option<usize>) -> Result<()> {
+        let mut pipeline = redis::Pipeline::new();
+        tuples.iter().for_each(|(key, value)| {
+            pipeline.set(key, value).ignore();
+            if let Some(expire) = expire {
+                pipeline.expire(key, expire).ignore();
+            };
+        });
+        Ok(self.query_atomic(pipeline).await?)
+    }
+
+    async fn del_multiple(&self, keys: &[String]) -> Result<()> {
+        let mut pipeline = redis::Pipeline::new();
+        keys.iter().for_each(|key| {
+            pipeline.del(key).ignore();
+        });
+        Ok(self.query_atomic(pipeline).await?)
+    }
+
+    async fn get_multiple(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let mut pipeline = redis::Pipeline::new();
+        keys.iter().for_each(|key| {
+            pipeline.get(key);
+        });
+        Ok(self.query_atomic(pipeline).await?)
+    }
+
+    async fn set_add(&self, key: &str, members: &[String]) -> Result<()> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        Ok(self
+            .run(|mut connection| async move { connection.sadd(key, members).await })
+            .await?)
+    }
+
+    async fn set_remove(&self, key: &str, members: &[String]) -> Result<()> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        Ok(self
+            .run(|mut connection| async move { connection.srem(key, members).await })
+            .await?)
+    }
+
+    async fn set_members(&self, key: &str) -> Result<Vec<String>> {
+        Ok(self
+            .run(|mut connection| async move { connection.smembers(key).await })
+            .await?)
+    }
+
+    async fn zadd_multiple(&self, key: &str, members: &[(i64, String)]) -> Result<()> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        Ok(self
+            .run(|mut connection| async move { connection.zadd_multiple(key, members).await })
+            .await?)
+    }
+
+    /// Members of the sorted set `key` with score up to `max`.
+    async fn zrange_by_score(&self, key: &str, max: i64) -> Result<Vec<String>> {
+        Ok(self
+            .run(|mut connection| async move { connection.zrangebyscore(key, "-inf", max).await })
+            .await?)
+    }
+
+    async fn zrem_by_score(&self, key: &str, max: i64) -> Result<()> {
+        Ok(self
+            .run(|mut connection| async move { connection.zrembyscore(key, "-inf", max).await })
+            .await?)
+    }
+
+    async fn apply(&self, batch: Batch) -> Result<bool> {
+        let condition = match &batch.condition {
+            None => {
+                let mut pipeline = redis::Pipeline::new();
+                batch.writes.iter().for_each(|write| {
+                    pipeline.add_command(write_command(write)).ignore();
+                });
+                self.query_atomic::<()>(pipeline).await?;
+                return Ok(true);
+            }
+            Some(condition) => condition,
+        };
+        let script = redis::Script::new(CONDITIONAL_BATCH_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation.key(condition.key());
+        match condition {
+            Condition::Exists(_) => invocation.arg("exists").arg(0),
+            Condition::Absent(_) => invocation.arg("absent").arg(0),
+            Condition::SetSmallerThan(_, size) => invocation.arg("smaller").arg(*size),
         };
-    });
-    pipeline.atomic().query_async(&mut connection).await?;
-    Ok(())
+        batch.writes.iter().for_each(|write| {
+            invocation.key(write.key());
+            match write {
+                Write::Set { value, expire, .. } => {
+                    invocation.arg("set").arg(value).arg(expire.unwrap_or(0))
+                }
+                Write::Del { .. } => invocation.arg("del").arg("").arg(""),
+                Write::SetAdd { member, .. } => invocation.arg("sadd").arg(member).arg(""),
+                Write::SetRemove { member, .. } => invocation.arg("srem").arg(member).arg(""),
+                Write::ZAdd { score, member, .. } => invocation.arg("zadd").arg(*score).arg(member),
+                Write::ZRem { member, .. } => invocation.arg("zrem").arg(member).arg(""),
+                Write::ZRemByScore { max, .. } => {
+                    invocation.arg("zremrangebyscore").arg(*max).arg("")
+                }
+            };
+        });
+        let invocation = &invocation;
+        let applied: i32 = self
+            .run(|mut connection| async move { invocation.invoke_async(&mut connection).await })
+            .await?;
+        Ok(applied == 1)
+    }
 }
 
-pub async fn del_multiple(keys: &[String]) -> RedisResult<()> {
-    let mut connection = get_connection().await?;
-    let mut pipeline = redis::Pipeline::new();
-    keys.iter().for_each(|key| {
-        pipeline.del(key);
-    });
-    pipeline.atomic().query_async(&mut connection).await?;
-    Ok(())
+fn is_connection_error(err: &RedisError) -> bool {
+    err.is_io_error() || err.is_connection_dropped() || err.is_connection_refusal()
 }
 
-pub async fn get_multiple(keys: &[String]) -> RedisResult<Vec<String>> {
-    let mut connection = get_connection().await?;
-    let mut pipeline = redis::Pipeline::new();
-    keys.iter().for_each(|key| {
-        pipeline.get(key);
-    });
-    let data: Vec<String> = pipeline.atomic().query_async(&mut connection).await?;
-    Ok(data)
+fn write_command(write: &Write) -> redis::Cmd {
+    let mut command;
+    match write {
+        Write::Set { key, value, expire } => {
+            command = redis::cmd("SET");
+            command.arg(key).arg(value);
+            if let Some(expire) = expire {
+                command.arg("EX").arg(*expire);
+            }
+        }
+        Write::Del { key } => {
+            command = redis::cmd("DEL");
+            command.arg(key);
+        }
+        Write::SetAdd { key, member } => {
+            command = redis::cmd("SADD");
+            command.arg(key).arg(member);
+        }
+        Write::SetRemove { key, member } => {
+            command = redis::cmd("SREM");
+            command.arg(key).arg(member);
+        }
+        Write::ZAdd { key, score, member } => {
+            command = redis::cmd("ZADD");
+            command.arg(key).arg(*score).arg(member);
+        }
+        Write::ZRem { key, member } => {
+            command = redis::cmd("ZREM");
+            command.arg(key).arg(member);
+        }
+        Write::ZRemByScore { key, max } => {
+            command = redis::cmd("ZREMRANGEBYSCORE");
+            command.arg(key).arg("-inf").arg(*max);
+        }
+    }
+    command
 }
@@ -0,0 +1,182 @@
+use crate::bike_service::{Geo, Station};
+use crate::station_history;
+use crate::store::Storage;
+use anyhow::Result;
+use std::cmp::Ordering;
+const LAST_NETWORK: &str = "LAST_NETWORK";
+
+/// Network of the last stations sent to a user. Searches without a location
+/// run on it.
+pub async fn last_network(storage: &Storage, user_id: i32) -> Result<Option<String>> {
+    storage.get_optional(&last_network_key(user_id)).await
+}
+
+/// Also keeps the network station history being recorded.
+pub async fn save_last_network(storage: &Storage, user_id: i32, network_href: &str) -> Result<()> {
+    storage
+        .set_multiple(
+            &[(last_network_key(user_id), network_href.to_string())],
+            None,
+        )
+        .await?;
+    station_history::track_network(storage, network_href).await
+}
+
+fn last_network_key(user_id: i32) -> String {
+    format!("{}:{}", LAST_NETWORK, user_id)
+}
+
+/// Stations matching every word of `query` on their name or address, best
+/// matches first. Ties are broken by distance when `location` is known.
+pub fn search<'a>(
+    stations: &'a [Station],
+    query: &str,
+    location: Option<&geoutils::Location>,
+    limit: usize,
+) -> Vec<&'a Station> {
+    let query_words = words(query);
+    // Emoji or punctuation only, every station would match
+    if query_words.is_empty() {
+        return vec![];
+    }
+    let mut found: Vec<(u32, &Station)> = stations
+        .iter()
+        .filter_map(|station| {
+            let station_words = words(&searchable_text(station));
+            let score = query_words
+                .iter()
+                .map(|word| word_score(word, &station_words))
+                .sum::<Option<u32>>()?;
+            Some((score, station))
+        })
+        .collect();
+    found.sort_by(|(a_score, a), (b_score, b)| {
+        let tie_break = match location {
+            Some(location) => {
+                let a = a.walk_from(location).meters;
+                let b = b.walk_from(location).meters;
+                a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+            }
+            None => a.name.cmp(&b.name),
+        };
+        b_score.cmp(a_score).then(tie_break)
+    });
+    found
+        .into_iter()
+        .take(limit)
+        .map(|(_, station)| station)
+        .collect()
+}
+
+/// How well `word` matches the best station word, None when no word is close.
+fn word_score(word: &str, station_words: &[String]) -> Option<u32> {
+    let max_typos = match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    };
+    station_words
+        .iter()
+        .filter_map(|station_word| {
+            if station_word == word {
+                Some(4)
+            } else if station_word.starts_with(word) {
+                Some(3)
+            } else if station_word.contains(word) {
+                Some(2)
+            } else if levenshtein(word, station_word) <= max_typos {
+                Some(1)
+            } else {
+                None
+            }
+        })
+        .max()
+}
+
+fn searchable_text(station: &Station) -> String {
+    let address = station
+        .extra
+        .as_ref()
+        .and_then(|extra| extra.address.as_deref())
+        .unwrap_or_default();
+    format!("{} {}", station.name, address)
+}
+
+/// Lowercase words without accents or punctuation, "Praça São Bento, 2"
+/// gives `praca`, `sao`, `bento` and `2`.
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ã' | 'ä' => 'a',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'õ' | 'ö' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'ç' => 'c',
+            'ñ' => 'n',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    fn station(name: &str) -> Station {
+        Station {
+            free_bikes: Some(1),
+            empty_slots: Some(1),
+            id: name.to_string(),
+            latitude: 38.72,
+            longitude: -9.14,
+            name: name.to_string(),
+            timestamp: Utc::now(),
+            extra: None,
+            network_href: None,
+            timezone: None,
+            prediction: None,
+        }
+    }
+
+    fn names(found: Vec<&Station>) -> Vec<&str> {
+        found.iter().map(|station| station.name.as_str()).collect()
+    }
+
+    #[test]
+    fn finds_stations_by_words_without_accents() {
+        let stations = vec![station("Praça São Bento"), station("Rossio")];
+        assert_eq!(
+            names(search(&stations, "sao bento", None, 5)),
+            vec!["Praça São Bento"]
+        );
+        assert_eq!(names(search(&stations, "rosio", None, 5)), vec!["Rossio"]);
+    }
+
+    #[test]
+    fn finds_nothing_without_query_words() {
+        let stations = vec![station("Praça São Bento"), station("Rossio")];
+        assert!(search(&stations, "🚲 !?", None, 5).is_empty());
+        assert!(search(&stations, "", None, 5).is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use crate::bike_service::{Provider, Station};
+use crate::store::{Batch, Storage};
+use anyhow::Result;
+use chrono::prelude::*;
+/// Sorted set of network hrefs scored by when they stop being recorded
+const HISTORY_NETWORKS: &str = "HISTORY_NETWORKS";
+const STATION_HISTORY: &str = "STATION_HISTORY";
+/// Set of the station history keys of a network, dropped with it
+const STATION_HISTORY_KEYS: &str = "STATION_HISTORY_KEYS";
+/// A week and a day, so the same weekday a week ago is still around
+pub const HISTORY_DAYS: i64 = 8;
+const HISTORY_SECONDS: i64 = 60 * 60 * 24 * HISTORY_DAYS;
+
+/// Availability of a station at `at`, stored as the `at:bikes:slots` member
+/// of the station history sorted set.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub at: i64,
+    pub free_bikes: u32,
+    pub empty_slots: u32,
+}
+
+impl Sample {
+    fn member(&self) -> String {
+        format!("{}:{}:{}", self.at, self.free_bikes, self.empty_slots)
+    }
+
+    fn parse(member: &str) -> Option<Self> {
+        let mut parts = member.split(':');
+        Some(Sample {
+            at: parts.next()?.parse().ok()?,
+            free_bikes: parts.next()?.parse().ok()?,
+            empty_slots: parts.next()?.parse().ok()?,
+        })
+    }
+}
+
+/// Average availability of the samples taken at `hour`, UTC.
+#[derive(Debug)]
+pub struct HourlyAverage {
+    pub hour: u32,
+    pub free_bikes: f32,
+    pub empty_slots: f32,
+}
+
+/// Keeps recording `network_href` stations for `HISTORY_DAYS` more days.
+pub async fn track_network(storage: &Storage, network_href: &str) -> Result<()> {
+    let until = Utc::now().timestamp() + HISTORY_SECONDS;
+    storage
+        .zadd_multiple(HISTORY_NETWORKS, &[(until, network_href.to_string())])
+        .await
+}
+
+/// Snapshots every station of the tracked networks, dropping samples older
+/// than `HISTORY_DAYS`, and the history of networks not tracked anymore.
+pub async fn collect_station_history(storage: &Storage, provider: &Provider) -> Result<()> {
+    let now = Utc::now().timestamp();
+    let expired_network_hrefs = storage.zrange_by_score(HISTORY_NETWORKS, now).await?;
+    storage.zrem_by_score(HISTORY_NETWORKS, now).await?;
+    for network_href in expired_network_hrefs {
+        let index = station_history_keys_key(&network_href);
+        let mut batch = Batch::new();
+        storage.set_members(&index).await?.iter().for_each(|key| {
+            batch.del(key);
+        });
+        batch.del(&index);
+        storage.apply(batch).await?;
+    }
+    let network_hrefs = storage.zrange_by_score(HISTORY_NETWORKS, i64::MAX).await?;
+    log::info!("Recording history of {} networks", network_hrefs.len());
+
+    for network_href in network_hrefs {
+        let stations = match provider.stations(&network_href).await {
+            Ok(stations) => stations,
+            Err(err) => {
+                log::error!("Error fetching {} stations {:?}", network_href, err);
+                continue;
+            }
+        };
+        let index = station_history_keys_key(&network_href);
+        let mut batch = Batch::new();
+        stations.iter().for_each(|station| {
+            if let (Some(free_bikes), Some(empty_slots)) = (station.free_bikes, station.empty_slots)
+            {
+                let sample = Sample {
+                    at: now,
+                    free_bikes,
+                    empty_slots,
+                };
+                let key = station_history_key(&network_href, &station.id);
+                batch
+                    .zadd(&key, sample.at, &sample.member())
+                    .zrem_by_score(&key, now - HISTORY_SECONDS)
+                    .set_add(&index, &key);
+            }
+        });
+        storage.apply(batch).await?;
+    }
+    Ok(())
+}
+
+/// Recorded samples of `station`, oldest first.
+pub async fn station_history(storage: &Storage, station: &Station) -> Result<Vec<Sample>> {
+    let network_href = station.network_href.as_deref().unwrap_or_default();
+    let samples = storage
+        .zrange_by_score(&station_history_key(network_href, &station.id), i64::MAX)
+        .await?
+        .iter()
+        .filter_map(|member| Sample::parse(member))
+        .collect();
+    Ok(samples)
+}
+
+/// Averages of the samples grouped by hour of the day, only hours with some.
+pub fn hourly_averages(samples: &[Sample]) -> Vec<HourlyAverage> {
+    let mut sums = [(0u32, 0u32, 0u32); 24];
+    for sample in samples {
+        let hour = Utc.timestamp(sample.at, 0).hour() as usize;
+        let (count, free_bikes, empty_slots) = &mut sums[hour];
+        *count += 1;
+        *free_bikes += sample.free_bikes;
+        *empty_slots += sample.empty_slots;
+    }
+    sums.iter()
+        .enumerate()
+        .filter(|(_, (count, _, _))| *count > 0)
+        .map(|(hour, (count, free_bikes, empty_slots))| HourlyAverage {
+            hour: hour as u32,
+            free_bikes: *free_bikes as f32 / *count as f32,
+            empty_slots: *empty_slots as f32 / *count as f32,
+        })
+        .collect()
+}
+
+fn station_history_key(network_href: &str, station_id: &str) -> String {
+    format!("{}:{}:{}", STATION_HISTORY, network_href, station_id)
+}
+
+fn station_history_keys_key(network_href: &str) -> String {
+    format!("{}:{}", STATION_HISTORY_KEYS, network_href)
+}
@@ -1,13 +1,19 @@
 // TODO think of a better name
-use crate::bike_service::Station;
+use crate::bike_service::{Geo, Provider, Station};
+use crate::handle_callback_query::{new_callback_uuid, save_callback_data};
+use crate::i18n::{fill, Lang};
 use crate::models::CallbackData;
 use crate::models::StationReminderInfo;
 use crate::models::StationWarn;
-use crate::redis_helper;
+use crate::models::WarnKind;
+use crate::models::WarnSettings;
+use crate::prediction;
+use crate::store::{Batch, Condition, Storage};
 use anyhow::Result;
 use chrono::prelude::*;
 use futures::future::join_all;
 use std::convert::TryFrom;
+use std::future::Future;
 use std::sync::Arc;
 use surf::Exception;
 use teloxide::prelude::*;
@@ -16,71 +22,256 @@ use teloxide::types::ParseMode;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 use teloxide::utils::markdown::{bold, escape};
 const LOW_PERCENTAGE_BIKES: f32 = 0.2; // 20%
-const WARN_INTERVAL_TIME: i64 = (60 * 5) - 5; // ~= 5 minutes
-const INLINE_KEYBOARD_DATA_TTL: usize = 60 * 60 * 6; // 6 hours
+const LOW_PERCENTAGE_SLOTS: f32 = 0.2; // 20%
 const ACTIVE_STATIONS_WARN: &str = "ACTIVE_STATIONS_WARN";
-pub const STATION_WARN_TTL: i64 = 60 * 30; // 30 minutes
+const CHAT_STATION_WARNS: &str = "CHAT_STATION_WARNS";
+/// Sorted set of warn keys scored by their next check timestamp
+const STATION_WARN_SCHEDULE: &str = "STATION_WARN_SCHEDULE";
+/// Sorted set of warn keys scored by their expiry timestamp
+const STATION_WARN_EXPIRY: &str = "STATION_WARN_EXPIRY";
+const STATION_WARN_KEY_GRACE: i64 = 60 * 10; // 10 minutes
 
 impl StationWarn {
     pub fn id(&self) -> String {
         format!("{}:{}", ACTIVE_STATIONS_WARN, self.uuid)
     }
 
-    pub fn should_warn(&self) -> bool {
-        let now = Utc::now();
-        now.timestamp() - self.updated_at.timestamp() > WARN_INTERVAL_TIME
+    pub fn next_check_at(&self) -> i64 {
+        self.updated_at.timestamp() + i64::from(self.settings.interval_minutes) * 60
+    }
+
+    pub fn expires_at(&self) -> i64 {
+        self.created_at.timestamp() + i64::from(self.settings.duration_minutes) * 60
     }
 
     pub fn should_delete(&self) -> bool {
-        let now = Utc::now();
-        now.timestamp() - self.created_at.timestamp() > STATION_WARN_TTL
+        self.remaining_seconds() < 0
+    }
+
+    pub fn remaining_seconds(&self) -> i64 {
+        self.expires_at() - Utc::now().timestamp()
+    }
+
+    /// Redis ttl of the warn key, the expiry schedule removes it before that.
+    fn key_ttl(&self) -> usize {
+        (self.remaining_seconds() + STATION_WARN_KEY_GRACE).max(1) as usize
+    }
+
+    /// Whether `amount` is inside the chat `below`/`above` thresholds, if any.
+    /// They are set for bikes and slots, e-bikes are always notified.
+    pub fn within_thresholds(&self, amount: u32) -> bool {
+        let WarnSettings { below, above, .. } = self.settings;
+        if self.kind == WarnKind::EBikes || (below.is_none() && above.is_none()) {
+            return true;
+        }
+        below.is_some_and(|below| amount < below) || above.is_some_and(|above| amount > above)
     }
 }
 
-fn reply_markup(station: &Station, uuid: &str) -> Option<InlineKeyboardMarkup> {
+fn remind_button(
+    station: &Station,
+    kind: WarnKind,
+    uuid: &str,
+    lang: Lang,
+) -> Option<InlineKeyboardButton> {
     let free_bikes = station.free_bikes? as f32;
     let empty_slots = station.empty_slots? as f32;
-    let show_warn = (free_bikes / (free_bikes + empty_slots)) <= LOW_PERCENTAGE_BIKES;
+    let capacity = free_bikes + empty_slots;
+    let catalog = lang.catalog();
+    let (show_warn, text) = match kind {
+        WarnKind::Bikes => (
+            free_bikes / capacity <= LOW_PERCENTAGE_BIKES,
+            catalog.remind_button,
+        ),
+        WarnKind::Slots => (
+            empty_slots / capacity <= LOW_PERCENTAGE_SLOTS,
+            catalog.remind_slots_button,
+        ),
+        // Offered only while there are no e-bikes at all
+        WarnKind::EBikes => (station.ebikes()? == 0, catalog.remind_ebikes_button),
+    };
 
     if !show_warn {
         return None;
     };
 
-    let button = InlineKeyboardButton::callback("Remind!".to_string(), uuid.into());
-    Some(InlineKeyboardMarkup::default().append_row(vec![button]))
+    Some(InlineKeyboardButton::callback(
+        text.to_string(),
+        uuid.into(),
+    ))
 }
 
-pub async fn reply_markups(stations: &[Station]) -> Result<Vec<Option<InlineKeyboardMarkup>>> {
-    // let station_warns: Vec<StationWarn> = stations.iter().map(|station| station.into()).collect();
-    let (reply_markups, station_reminders): (
-        Vec<Option<InlineKeyboardMarkup>>,
-        Vec<Option<StationReminderInfo>>,
-    ) = stations
+/// Reminder buttons for each station, one per `WarnKind` running low, or a
+/// "Stop" button for the warns `chat_id` already has. The walk from
+/// `user_location` is kept to be shown in the notifications.
+pub async fn remind_buttons(
+    storage: &Storage,
+    chat_id: i64,
+    stations: &[Station],
+    user_location: Option<&geoutils::Location>,
+    lang: Lang,
+) -> Result<Vec<Vec<InlineKeyboardButton>>> {
+    let active_warns = chat_station_warns(storage, chat_id).await?;
+    let mut callback_data: Vec<(String, CallbackData)> = vec![];
+    let buttons = stations
         .iter()
         .map(|station| {
-            let station_reminder_info = StationReminderInfo::try_from(station.clone());
-            match station_reminder_info {
-                Ok(value) => match reply_markup(station, &value.uuid) {
-                    Some(rm) => (Some(rm), Some(value)),
-                    None => (None, None),
-                },
-                Err(_) => (None, None),
-            }
+            [WarnKind::Bikes, WarnKind::Slots, WarnKind::EBikes]
+                .iter()
+                .filter_map(|&kind| {
+                    let active_warn = active_warns.iter().find(|station_warn| {
+                        station_warn.kind == kind
+                            && station_warn.station_info.id == station.id
+                            && station.network_href.as_ref()
+                                == Some(&station_warn.station_info.network_href)
+                    });
+                    if let Some(stop_callback) =
+                        active_warn.and_then(|station_warn| station_warn.stop_callback.clone())
+                    {
+                        let text = lang.catalog().stop_button.to_string();
+                        return Some(InlineKeyboardButton::callback(text, stop_callback));
+                    }
+                    let mut station_reminder =
+                        StationReminderInfo::try_from(station.clone()).ok()?;
+                    station_reminder.walk =
+                        user_location.map(|location| station.walk_from(location));
+                    let button = remind_button(station, kind, &station_reminder.uuid, lang)?;
+                    callback_data.push((
+                        station_reminder.uuid.clone(),
+                        CallbackData::station_reminder(kind, station_reminder),
+                    ));
+                    Some(button)
+                })
+                .collect()
         })
-        .unzip();
-    let key_value: Vec<(String, String)> = station_reminders
+        .collect();
+    save_callback_data(storage, callback_data).await?;
+
+    Ok(buttons)
+}
+
+/// Warns of a chat on the same station and kind share this uuid, so a
+/// reminder button tapped twice watches the station once.
+pub fn station_warn_uuid(
+    chat_id: i64,
+    station_info: &StationReminderInfo,
+    kind: WarnKind,
+) -> String {
+    format!(
+        "{}:{}:{}:{:?}",
+        chat_id, station_info.network_href, station_info.id, kind
+    )
+}
+
+/// Saves a new warn, indexing it under its chat and creating the callback
+/// data of its "Stop" button. When the warn is already active `station_warn`
+/// is replaced with it instead.
+pub async fn save_station_warn(storage: &Storage, station_warn: &mut StationWarn) -> Result<()> {
+    let stop_callback = new_callback_uuid();
+    let callback_data = CallbackData::StopStationReminder {
+        warn_uuid: station_warn.uuid.clone(),
+        refresh_list: false,
+    };
+    save_callback_data(storage, vec![(stop_callback.clone(), callback_data)]).await?;
+    station_warn.stop_callback = Some(stop_callback);
+
+    let data = serde_json::to_string(&station_warn)?;
+    let key = station_warn.id();
+    let mut batch = Batch::when(Condition::Absent(key.clone()));
+    batch
+        .set(&key, &data, Some(station_warn.key_ttl()))
+        .zadd(STATION_WARN_SCHEDULE, station_warn.next_check_at(), &key)
+        .zadd(STATION_WARN_EXPIRY, station_warn.expires_at(), &key)
+        .set_add(&chat_station_warns_key(station_warn.chat_id), &key);
+    if storage.apply(batch.clone()).await? {
+        return Ok(());
+    }
+    match find_station_warn(storage, &station_warn.uuid).await? {
+        Some(existing) if !existing.should_delete() => *station_warn = existing,
+        // Expired but not dropped by the warn loop yet
+        existing => {
+            unschedule(storage, &[key], existing.as_slice()).await?;
+            storage.apply(batch).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn delete_station_warns(storage: &Storage, station_warns: &[StationWarn]) -> Result<()> {
+    let keys: Vec<_> = station_warns.iter().map(StationWarn::id).collect();
+    unschedule(storage, &keys, station_warns).await
+}
+
+/// Removes warn `keys` from storage and schedules. `station_warns` are the
+/// ones that could still be read, needed to clean up their chat index.
+async fn unschedule(
+    storage: &Storage,
+    keys: &[String],
+    station_warns: &[StationWarn],
+) -> Result<()> {
+    let mut batch = Batch::new();
+    keys.iter().for_each(|key| {
+        batch
+            .del(key)
+            .zrem(STATION_WARN_SCHEDULE, key)
+            .zrem(STATION_WARN_EXPIRY, key);
+    });
+    station_warns.iter().for_each(|station_warn| {
+        batch.set_remove(
+            &chat_station_warns_key(station_warn.chat_id),
+            &station_warn.id(),
+        );
+    });
+    storage.apply(batch).await?;
+    Ok(())
+}
+
+async fn load_station_warns(storage: &Storage, keys: &[String]) -> Result<Vec<StationWarn>> {
+    let station_warns = storage
+        .get_multiple(keys)
+        .await?
         .into_iter()
-        .filter_map(|sr| sr)
-        .map(|station_reminder| {
-            let uuid = station_reminder.uuid.clone();
-            let callback_data: CallbackData = station_reminder.into();
-            let callback_data = serde_json::to_string(&callback_data).unwrap_or_default();
-            (uuid, callback_data)
-        })
+        .flatten()
+        .filter_map(|data| serde_json::from_str(&data).ok())
+        .collect();
+    Ok(station_warns)
+}
+
+pub async fn find_station_warn(storage: &Storage, uuid: &str) -> Result<Option<StationWarn>> {
+    let key = format!("{}:{}", ACTIVE_STATIONS_WARN, uuid);
+    let station_warn = match storage.get_optional(&key).await? {
+        Some(data) => Some(serde_json::from_str(&data)?),
+        None => None,
+    };
+    Ok(station_warn)
+}
+
+/// Active warns of a chat, oldest first.
+pub async fn chat_station_warns(storage: &Storage, chat_id: i64) -> Result<Vec<StationWarn>> {
+    let keys = storage
+        .set_members(&chat_station_warns_key(chat_id))
+        .await?;
+    let mut station_warns: Vec<StationWarn> = storage
+        .get_multiple(&keys)
+        .await?
+        .into_iter()
+        .flatten()
+        .filter_map(|data| serde_json::from_str(&data).ok())
+        .filter(|station_warn: &StationWarn| !station_warn.should_delete())
         .collect();
-    redis_helper::set_multiple(&key_value, Some(INLINE_KEYBOARD_DATA_TTL)).await?;
+    station_warns.sort_by_key(|station_warn| station_warn.created_at);
+    Ok(station_warns)
+}
 
-    Ok(reply_markups)
+fn chat_station_warns_key(chat_id: i64) -> String {
+    format!("{}:{}", CHAT_STATION_WARNS, chat_id)
+}
+
+fn stop_keyboard(station_warn: &StationWarn) -> Option<InlineKeyboardMarkup> {
+    let uuid = station_warn.stop_callback.clone()?;
+    let text = station_warn.lang.catalog().stop_button.to_string();
+    let button = InlineKeyboardButton::callback(text, uuid);
+    Some(InlineKeyboardMarkup::default().append_row(vec![button]))
 }
 
 pub fn build_telegram_message(
@@ -88,23 +279,84 @@ pub fn build_telegram_message(
     updated_station: &Station,
     bot: Arc<Bot>,
 ) -> Option<SendMessage> {
-    let updated_station_free_bikes = updated_station.free_bikes?;
-    let free_bikes_diff =
-        updated_station_free_bikes as i32 - station_warn.station_info.free_bikes as i32;
-    let message = match free_bikes_diff {
-        i32::MIN..=-1 => format!(
-            "💔 `{}` has lost {} bikes",
-            escape(&updated_station.name),
-            bold(&free_bikes_diff.abs().to_string())
+    let catalog = station_warn.lang.catalog();
+    let station_info = &station_warn.station_info;
+    let (updated_amount, previous_amount, lost, appeared) = match station_warn.kind {
+        WarnKind::Bikes => (
+            updated_station.free_bikes?,
+            station_info.free_bikes,
+            Some(catalog.bikes_lost),
+            catalog.bikes_appeared,
+        ),
+        WarnKind::Slots => (
+            updated_station.empty_slots?,
+            station_info.empty_slots,
+            Some(catalog.slots_lost),
+            catalog.slots_appeared,
+        ),
+        WarnKind::EBikes => (
+            updated_station.ebikes()?,
+            station_info.ebikes,
+            None,
+            catalog.ebikes_appeared,
+        ),
+    };
+    if !station_warn.within_thresholds(updated_amount) {
+        return None;
+    }
+    let diff = updated_amount as i32 - previous_amount as i32;
+    let message = match diff {
+        i32::MIN..=-1 => fill(
+            lost?,
+            &[
+                ("station", &escape(&updated_station.name)),
+                ("amount", &bold(&diff.abs().to_string())),
+            ],
         ),
         0 => return None,
-        1..=i32::MAX => format!(
-            "💚 {} has appeard on `{}`!!! It now has {} bikes.",
-            bold(&free_bikes_diff.to_string()),
-            escape(&updated_station.name),
-            bold(&updated_station_free_bikes.to_string())
+        1..=i32::MAX => fill(
+            appeared,
+            &[
+                ("amount", &bold(&diff.to_string())),
+                ("station", &escape(&updated_station.name)),
+                ("total", &bold(&updated_amount.to_string())),
+            ],
         ),
     };
+    // Its numbers may be off, as in the listing
+    let message = if updated_station.is_stale() {
+        format!("{} {}", message, escape(catalog.stale_data))
+    } else {
+        message
+    };
+    let forecast = updated_station
+        .prediction
+        .and_then(|prediction| match station_warn.kind {
+            WarnKind::Bikes => Some(prediction.free_bikes),
+            WarnKind::Slots => Some(prediction.empty_slots),
+            WarnKind::EBikes => None,
+        });
+    let message = match station_info.walk {
+        Some(walk) => {
+            let walk_line = format!("{}: {}", catalog.distance_label, walk);
+            format!("{}\n{}", message, escape(&walk_line))
+        }
+        None => message,
+    };
+    let message = match forecast {
+        Some(forecast) => {
+            let forecast_line = fill(
+                catalog.forecast_line,
+                &[
+                    ("trend", forecast.trend(updated_amount)),
+                    ("in_15", &forecast.in_15.to_string()),
+                    ("in_30", &forecast.in_30.to_string()),
+                ],
+            );
+            format!("{}\n{}", message, escape(&forecast_line))
+        }
+        None => message,
+    };
 
     // Build telegram message
     let chat_id = station_warn.chat_id;
@@ -114,37 +366,87 @@ pub fn build_telegram_message(
         .send_message(chat_id, message)
         .reply_to_message_id(message_id)
         .parse_mode(ParseMode::MarkdownV2);
+    let send_message = match stop_keyboard(station_warn) {
+        Some(keyboard) => send_message.reply_markup(keyboard),
+        None => send_message,
+    };
     Some(send_message)
 }
-pub async fn check_active_warn_stations(bot: Arc<Bot>) -> Result<(), Exception> {
-    let keys = redis_helper::keys(Some(&format!("{}*", ACTIVE_STATIONS_WARN))).await?;
-    log::info!("Found {} station warns", &keys.len());
-    let (old_station_warns, active_station_warns): (Vec<_>, Vec<_>) =
-        redis_helper::get_multiple(&keys)
-            .await?
-            .into_iter()
-            .filter_map(|data| serde_json::from_str(&data).ok())
-            .partition(StationWarn::should_delete);
-    // Delte old station warns that have passed their ttl
-    let old_station_warns_keys: Vec<_> =
-        old_station_warns.into_iter().map(|osw| osw.id()).collect();
-    log::debug!("Deleting StationWarn: {:?}", &old_station_warns_keys); // TODO remove this
-    redis_helper::del_multiple(&old_station_warns_keys).await?;
-
-    let mut stations_to_be_warned: Vec<StationWarn> = active_station_warns
+/// Drops expired warns and checks the ones due, both picked by score from
+/// the schedule sorted sets.
+pub async fn check_active_warn_stations(
+    storage: &Storage,
+    provider: &Provider,
+    bot: Arc<Bot>,
+) -> Result<(), Exception> {
+    let send_messages = check_station_warns(storage, bot, |id, network_href| async move {
+        Station::fetch(provider.as_ref(), &id, &network_href).await
+    })
+    .await?;
+    let send_messages: Vec<_> = send_messages
+        .iter()
+        .map(|send_message| send_message.send())
+        .collect();
+
+    log::debug!("{} messages to be sent", &send_messages.len());
+    let results: Vec<_> = join_all(send_messages).await;
+    results
+        .iter()
+        .filter_map(|r| r.as_ref().err())
+        .for_each(|err| log::error!("Error sending message {:?}", err));
+    Ok(())
+}
+
+/// Updates the due warns with the station `fetch` returns for their station
+/// id and network href, returning the notifications to send.
+async fn check_station_warns<F, Fut>(
+    storage: &Storage,
+    bot: Arc<Bot>,
+    fetch: F,
+) -> Result<Vec<SendMessage>, Exception>
+where
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = Result<Station, Exception>>,
+{
+    let now = Utc::now().timestamp();
+    let expired_keys = storage.zrange_by_score(STATION_WARN_EXPIRY, now).await?;
+    let expired_station_warns = load_station_warns(storage, &expired_keys).await?;
+    log::debug!("Deleting {} expired StationWarn", expired_keys.len());
+    unschedule(storage, &expired_keys, &expired_station_warns).await?;
+
+    let due_keys = storage.zrange_by_score(STATION_WARN_SCHEDULE, now).await?;
+    let mut stations_to_be_warned = load_station_warns(storage, &due_keys).await?;
+    log::info!("{} StationWarn are due", &stations_to_be_warned.len());
+    // Keys whose data is gone, e.g. expired by redis ttl
+    let missing_keys: Vec<String> = due_keys
         .into_iter()
-        .filter(StationWarn::should_warn)
+        .filter(|key| {
+            !stations_to_be_warned
+                .iter()
+                .any(|station_warn| &station_warn.id() == key)
+        })
         .collect();
-    log::info!(
-        "{} StationWarn are older than 5 minutes",
-        &stations_to_be_warned.len()
-    );
+    unschedule(storage, &missing_keys, &[]).await?;
 
     let updated_stations: Vec<_> = stations_to_be_warned
         .iter()
-        .map(|w| Station::fetch(&w.station_info.id, &w.station_info.network_href))
+        .map(|w| {
+            fetch(
+                w.station_info.id.clone(),
+                w.station_info.network_href.clone(),
+            )
+        })
         .collect();
-    let updated_stations: Vec<Result<Station, Exception>> = join_all(updated_stations).await;
+    let mut updated_stations: Vec<Result<Station, Exception>> = join_all(updated_stations).await;
+    join_all(
+        stations_to_be_warned
+            .iter()
+            .zip(updated_stations.iter_mut())
+            .map(|(station_warn, station)| {
+                prediction::annotate(storage, station_warn.chat_id, station)
+            }),
+    )
+    .await;
     let send_messages: Vec<_> = stations_to_be_warned
         .iter_mut()
         .zip(updated_stations.iter())
@@ -156,36 +458,335 @@ pub async fn check_active_warn_stations(bot: Arc<Bot>) -> Result<(), Exception>
             }
         })
         .map(|(station_warn, updated_station)| {
-            let send_message = build_telegram_message(station_warn, updated_station, bot.clone());
+            // Nothing new upstream since the last check
+            let unchanged = station_warn.station_info.timestamp == Some(updated_station.timestamp);
+            let send_message = if unchanged {
+                None
+            } else {
+                build_telegram_message(station_warn, updated_station, bot.clone())
+            };
             // updated station warn info
             station_warn.updated_at = Utc::now();
             station_warn.station_info.free_bikes = updated_station.free_bikes.unwrap_or_default();
+            station_warn.station_info.empty_slots = updated_station.empty_slots.unwrap_or_default();
+            station_warn.station_info.ebikes = updated_station.ebikes().unwrap_or_default();
+            station_warn.station_info.timestamp = Some(updated_station.timestamp);
 
-            send_message
+            (station_warn.id(), send_message)
         })
-        .filter_map(|message| message)
         .collect();
 
-    let saves: Vec<(String, String)> = stations_to_be_warned
-        .iter()
-        .map(|station_warn| {
-            let key = format!("{}:{}", ACTIVE_STATIONS_WARN, station_warn.uuid);
-            let data = serde_json::to_string(station_warn).unwrap_or_default();
-            (key, data)
+    // Save updated info and next check time together, unless the warn was
+    // stopped or expired while its station was fetched
+    let mut active_keys = vec![];
+    for station_warn in &stations_to_be_warned {
+        let data = serde_json::to_string(station_warn)?;
+        let key = station_warn.id();
+        let mut update = Batch::when(Condition::Exists(key.clone()));
+        update.set(&key, &data, Some(station_warn.key_ttl())).zadd(
+            STATION_WARN_SCHEDULE,
+            station_warn.next_check_at(),
+            &key,
+        );
+        if storage.apply(update).await? {
+            active_keys.push(key);
+        } else {
+            let mut unschedule = Batch::new();
+            unschedule.zrem(STATION_WARN_SCHEDULE, &key);
+            storage.apply(unschedule).await?;
+        }
+    }
+
+    Ok(send_messages
+        .into_iter()
+        .filter(|(key, _)| active_keys.contains(key))
+        .filter_map(|(_, send_message)| send_message)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bike_service::Extra;
+    use crate::store::MemoryStore;
+    use chrono::Duration;
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    fn storage() -> Storage {
+        Arc::new(MemoryStore::default())
+    }
+
+    fn bot() -> Arc<Bot> {
+        Bot::new("test")
+    }
+
+    fn station(free_bikes: u32, timestamp: DateTime<Utc>) -> Station {
+        Station {
+            free_bikes: Some(free_bikes),
+            empty_slots: Some(10 - free_bikes),
+            id: "station".to_string(),
+            latitude: 38.72,
+            longitude: -9.14,
+            name: "Rossio".to_string(),
+            timestamp,
+            extra: None,
+            network_href: Some("/v2/networks/test".to_string()),
+            timezone: None,
+            prediction: None,
+        }
+    }
+
+    /// Warn on a station with 1 bike, created and last checked `age` ago.
+    fn station_warn(age: Duration) -> StationWarn {
+        let checked_at = Utc::now() - age;
+        let mut station_info = StationReminderInfo::try_from(station(1, checked_at)).unwrap();
+        station_info.uuid = new_callback_uuid();
+        StationWarn {
+            uuid: station_info.uuid.clone(),
+            message_id: 1,
+            created_at: checked_at,
+            updated_at: checked_at,
+            chat_id: 42,
+            station_info,
+            lang: Lang::En,
+            kind: WarnKind::Bikes,
+            settings: WarnSettings::default(),
+            stop_callback: None,
+        }
+    }
+
+    async fn unreachable_fetch(_: String, _: String) -> Result<Station, Exception> {
+        panic!("No warn should be due")
+    }
+
+    #[tokio::test]
+    async fn creates_a_warn() {
+        let storage = storage();
+        let mut station_warn = station_warn(Duration::zero());
+        save_station_warn(&storage, &mut station_warn)
+            .await
+            .unwrap();
+
+        assert!(station_warn.stop_callback.is_some());
+        let found = find_station_warn(&storage, &station_warn.uuid)
+            .await
+            .unwrap();
+        assert_eq!(found.unwrap().stop_callback, station_warn.stop_callback);
+        let chat_warns = chat_station_warns(&storage, 42).await.unwrap();
+        assert_eq!(chat_warns.len(), 1);
+        assert_eq!(chat_warns[0].uuid, station_warn.uuid);
+    }
+
+    #[tokio::test]
+    async fn keeps_a_single_warn_per_station_and_kind() {
+        let storage = storage();
+        let new_warn = || {
+            let mut station_warn = station_warn(Duration::zero());
+            station_warn.uuid = station_warn_uuid(42, &station_warn.station_info, WarnKind::Bikes);
+            station_warn
+        };
+        let mut first = new_warn();
+        save_station_warn(&storage, &mut first).await.unwrap();
+        let mut second = new_warn();
+        save_station_warn(&storage, &mut second).await.unwrap();
+
+        assert_eq!(second.stop_callback, first.stop_callback);
+        assert_eq!(chat_station_warns(&storage, 42).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn offers_stop_for_active_warns() {
+        let storage = storage();
+        let mut station_warn = station_warn(Duration::zero());
+        save_station_warn(&storage, &mut station_warn)
+            .await
+            .unwrap();
+
+        let stations = [station(1, Utc::now())];
+        let buttons = remind_buttons(&storage, 42, &stations, None, Lang::En)
+            .await
+            .unwrap();
+        let stop_callback = station_warn.stop_callback.unwrap();
+        assert!(buttons[0].iter().any(|button| matches!(
+            &button.kind,
+            InlineKeyboardButtonKind::CallbackData(data) if *data == stop_callback
+        )));
+        let other_chat = remind_buttons(&storage, 7, &stations, None, Lang::En)
+            .await
+            .unwrap();
+        assert!(other_chat[0]
+            .iter()
+            .all(|button| button.text != Lang::En.catalog().stop_button));
+    }
+
+    #[tokio::test]
+    async fn checks_only_due_warns() {
+        let storage = storage();
+        let mut station_warn = station_warn(Duration::zero());
+        save_station_warn(&storage, &mut station_warn)
+            .await
+            .unwrap();
+
+        let messages = check_station_warns(&storage, bot(), unreachable_fetch)
+            .await
+            .unwrap();
+        assert!(messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn notifies_and_reschedules_due_warns() {
+        let storage = storage();
+        let mut station_warn = station_warn(Duration::minutes(6));
+        save_station_warn(&storage, &mut station_warn)
+            .await
+            .unwrap();
+
+        let messages =
+            check_station_warns(&storage, bot(), |_, _| async { Ok(station(4, Utc::now())) })
+                .await
+                .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].text.contains("Rossio"));
+
+        let updated = find_station_warn(&storage, &station_warn.uuid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.station_info.free_bikes, 4);
+        assert!(updated.next_check_at() > Utc::now().timestamp());
+        // Rescheduled, not due again right away
+        let messages = check_station_warns(&storage, bot(), unreachable_fetch)
+            .await
+            .unwrap();
+        assert!(messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn skips_warns_without_new_station_data() {
+        let storage = storage();
+        let mut station_warn = station_warn(Duration::minutes(6));
+        save_station_warn(&storage, &mut station_warn)
+            .await
+            .unwrap();
+        let reported_at = station_warn.station_info.timestamp.unwrap();
+
+        let messages = check_station_warns(&storage, bot(), |_, _| async move {
+            Ok(station(4, reported_at))
         })
-        .collect();
-    redis_helper::set_multiple(&saves, None).await?;
+        .await
+        .unwrap();
+        assert!(messages.is_empty());
+    }
 
-    let send_messages: Vec<_> = send_messages
-        .iter()
-        .map(|send_message| send_message.send())
-        .collect();
+    #[tokio::test]
+    async fn stops_a_warn() {
+        let storage = storage();
+        let mut station_warn = station_warn(Duration::minutes(6));
+        save_station_warn(&storage, &mut station_warn)
+            .await
+            .unwrap();
 
-    log::debug!("{} messages to be sent", &send_messages.len());
-    let results: Vec<_> = join_all(send_messages).await;
-    results
-        .iter()
-        .filter_map(|r| r.as_ref().err())
-        .for_each(|err| log::error!("Error sending message {:?}", err));
-    Ok(())
+        delete_station_warns(&storage, &[station_warn])
+            .await
+            .unwrap();
+        assert!(chat_station_warns(&storage, 42).await.unwrap().is_empty());
+        let messages = check_station_warns(&storage, bot(), unreachable_fetch)
+            .await
+            .unwrap();
+        assert!(messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn does_not_bring_back_a_warn_stopped_while_checking() {
+        let storage = storage();
+        let mut station_warn = station_warn(Duration::minutes(6));
+        save_station_warn(&storage, &mut station_warn)
+            .await
+            .unwrap();
+        let uuid = station_warn.uuid.clone();
+
+        let messages = check_station_warns(&storage, bot(), |_, _| {
+            let storage = storage.clone();
+            let uuid = uuid.clone();
+            async move {
+                let station_warn = find_station_warn(&storage, &uuid).await?.unwrap();
+                delete_station_warns(&storage, &[station_warn]).await?;
+                Ok(station(4, Utc::now()))
+            }
+        })
+        .await
+        .unwrap();
+        assert!(messages.is_empty());
+        assert!(find_station_warn(&storage, &uuid).await.unwrap().is_none());
+        let schedule = storage
+            .zrange_by_score(STATION_WARN_SCHEDULE, i64::MAX)
+            .await
+            .unwrap();
+        assert!(schedule.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expires_a_warn() {
+        let storage = storage();
+        let mut station_warn = station_warn(Duration::minutes(31));
+        save_station_warn(&storage, &mut station_warn)
+            .await
+            .unwrap();
+
+        let messages = check_station_warns(&storage, bot(), unreachable_fetch)
+            .await
+            .unwrap();
+        assert!(messages.is_empty());
+        assert!(find_station_warn(&storage, &station_warn.uuid)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(chat_station_warns(&storage, 42).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn flags_stale_station_data() {
+        let station_warn = station_warn(Duration::zero());
+        let fresh = build_telegram_message(&station_warn, &station(3, Utc::now()), bot()).unwrap();
+        assert!(!fresh.text.contains(Lang::En.catalog().stale_data));
+
+        let reported_at = Utc::now() - Duration::hours(2);
+        let stale = build_telegram_message(&station_warn, &station(3, reported_at), bot()).unwrap();
+        assert!(stale.text.contains(Lang::En.catalog().stale_data));
+    }
+
+    /// Station of an e-bike network, with `ebikes` of its `free_bikes`.
+    fn ebike_station(free_bikes: u32, empty_slots: u32, ebikes: u32) -> Station {
+        Station {
+            empty_slots: Some(empty_slots),
+            extra: Some(Extra {
+                ebikes: Some(ebikes),
+                has_ebikes: Some(true),
+                ..Extra::default()
+            }),
+            ..station(free_bikes, Utc::now())
+        }
+    }
+
+    #[test]
+    fn offers_ebike_reminders_only_without_ebikes() {
+        let remind = |station: &Station| remind_button(station, WarnKind::EBikes, "uuid", Lang::En);
+        assert!(remind(&ebike_station(4, 6, 0)).is_some());
+        assert!(remind(&ebike_station(4, 6, 2)).is_none());
+        // A station with nothing at all reported
+        assert!(remind(&ebike_station(0, 0, 0)).is_some());
+        assert!(remind(&station(4, Utc::now())).is_none());
+    }
+
+    #[test]
+    fn notifies_ebikes_regardless_of_the_bike_thresholds() {
+        let mut station_warn = station_warn(Duration::zero());
+        station_warn.settings.below = Some(2);
+        assert!(!station_warn.within_thresholds(3));
+
+        station_warn.kind = WarnKind::EBikes;
+        assert!(station_warn.within_thresholds(3));
+        let message = build_telegram_message(&station_warn, &ebike_station(5, 5, 3), bot());
+        assert!(message.is_some());
+    }
 }
@@ -0,0 +1,305 @@
+use crate::bike_service::Station;
+use crate::models::StationMode;
+use anyhow::Result;
+const MAP_SIZE: u32 = 512; // pixels
+const MAP_MARGIN: f64 = 40.0; // pixels
+const MIN_SPAN: f64 = 0.002; // degrees, about 200 meters
+const GRID_STEP: u32 = 64; // pixels
+const STATION_RADIUS: i32 = 14;
+const USER_RADIUS: i32 = 9;
+const DIGIT_SCALE: i32 = 3;
+
+type Color = [u8; 3];
+const BACKGROUND: Color = [246, 244, 240];
+const GRID: Color = [225, 222, 216];
+const WHITE: Color = [255, 255, 255];
+const USER: Color = [33, 115, 230];
+const UNKNOWN: Color = [150, 150, 150];
+const EMPTY: Color = [214, 48, 49];
+const FEW: Color = [240, 160, 20];
+const PLENTY: Color = [39, 174, 96];
+const FEW_LIMIT: u32 = 3;
+
+/// 3x5 digits, each row holds 3 bits from left to right.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// PNG plot of the user position and `stations`, numbered from `first_position`
+/// as in the listing. Stations are colored by what `mode` needs, bikes unless
+/// it's a slot: red with none, orange with a few and green otherwise.
+pub fn render(
+    user_location: &geoutils::Location,
+    stations: &[Station],
+    first_position: usize,
+    mode: StationMode,
+) -> Result<Vec<u8>> {
+    let projection = Projection::new(user_location, stations);
+    let mut canvas = Canvas::new(MAP_SIZE, MAP_SIZE);
+    canvas.draw_grid();
+
+    for (index, station) in stations.iter().enumerate() {
+        let (x, y) = projection.point(station.latitude, station.longitude);
+        let amount = match mode {
+            StationMode::NeedSlot => station.empty_slots,
+            StationMode::Any | StationMode::NeedBike => station.free_bikes,
+        };
+        let color = match amount {
+            None => UNKNOWN,
+            Some(0) => EMPTY,
+            Some(amount) if amount <= FEW_LIMIT => FEW,
+            Some(_) => PLENTY,
+        };
+        canvas.fill_circle(x, y, STATION_RADIUS + 2, WHITE);
+        canvas.fill_circle(x, y, STATION_RADIUS, color);
+        canvas.draw_number(x, y, first_position + index, WHITE);
+    }
+
+    let (x, y) = projection.point(user_location.latitude(), user_location.longitude());
+    canvas.fill_circle(x, y, USER_RADIUS + 3, WHITE);
+    canvas.fill_circle(x, y, USER_RADIUS, USER);
+
+    canvas.encode()
+}
+
+/// Equirectangular projection fitting every point in the map, good enough at
+/// the scale of a neighbourhood.
+struct Projection {
+    center_latitude: f64,
+    center_longitude: f64,
+    longitude_scale: f64,
+    pixels_per_degree: f64,
+}
+
+impl Projection {
+    fn new(user_location: &geoutils::Location, stations: &[Station]) -> Self {
+        let latitudes = stations
+            .iter()
+            .map(|station| station.latitude)
+            .chain(Some(user_location.latitude()));
+        let longitudes = stations
+            .iter()
+            .map(|station| station.longitude)
+            .chain(Some(user_location.longitude()));
+        let (min_latitude, max_latitude) = bounds(latitudes);
+        let (min_longitude, max_longitude) = bounds(longitudes);
+
+        let center_latitude = (min_latitude + max_latitude) / 2.0;
+        let longitude_scale = center_latitude.to_radians().cos();
+        let span = (max_latitude - min_latitude)
+            .max((max_longitude - min_longitude) * longitude_scale)
+            .max(MIN_SPAN);
+        Projection {
+            center_latitude,
+            center_longitude: (min_longitude + max_longitude) / 2.0,
+            longitude_scale,
+            pixels_per_degree: (f64::from(MAP_SIZE) - 2.0 * MAP_MARGIN) / span,
+        }
+    }
+
+    fn point(&self, latitude: f64, longitude: f64) -> (i32, i32) {
+        let half = f64::from(MAP_SIZE) / 2.0;
+        let x = half
+            + (longitude - self.center_longitude) * self.longitude_scale * self.pixels_per_degree;
+        let y = half - (latitude - self.center_latitude) * self.pixels_per_degree;
+        (x.round() as i32, y.round() as i32)
+    }
+}
+
+fn bounds(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values.fold((f64::MAX, f64::MIN), |(min, max), value| {
+        (min.min(value), max.max(value))
+    })
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        let pixels = BACKGROUND
+            .iter()
+            .cycle()
+            .take((width * height) as usize * 3)
+            .cloned()
+            .collect();
+        Canvas {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn set(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+        let offset = (y as usize * self.width as usize + x as usize) * 3;
+        self.pixels[offset..offset + 3].copy_from_slice(&color);
+    }
+
+    fn draw_grid(&mut self) {
+        for position in (GRID_STEP..self.width.max(self.height)).step_by(GRID_STEP as usize) {
+            for step in 0..self.width.max(self.height) {
+                self.set(position as i32, step as i32, GRID);
+                self.set(step as i32, position as i32, GRID);
+            }
+        }
+    }
+
+    fn fill_circle(&mut self, center_x: i32, center_y: i32, radius: i32, color: Color) {
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                if x * x + y * y <= radius * radius {
+                    self.set(center_x + x, center_y + y, color);
+                }
+            }
+        }
+    }
+
+    /// `number` centered on the point, with the digits of `DIGITS`.
+    fn draw_number(&mut self, center_x: i32, center_y: i32, number: usize, color: Color) {
+        let digits: Vec<usize> = number
+            .to_string()
+            .chars()
+            .filter_map(|c| c.to_digit(10))
+            .map(|digit| digit as usize)
+            .collect();
+        let digit_width = 3 * DIGIT_SCALE;
+        let spacing = DIGIT_SCALE;
+        let width = digits.len() as i32 * (digit_width + spacing) - spacing;
+        let left = center_x - width / 2;
+        let top = center_y - 5 * DIGIT_SCALE / 2;
+        for (index, digit) in digits.into_iter().enumerate() {
+            let digit_left = left + index as i32 * (digit_width + spacing);
+            for (row, bits) in DIGITS[digit].iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..DIGIT_SCALE {
+                        for dx in 0..DIGIT_SCALE {
+                            self.set(
+                                digit_left + column * DIGIT_SCALE + dx,
+                                top + row as i32 * DIGIT_SCALE + dy,
+                                color,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut data, self.width, self.height);
+            encoder.set_color(png::ColorType::RGB);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.pixels)?;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    /// Set `UPDATE_SNAPSHOTS=1` to write the rendered map over it.
+    const SNAPSHOT: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/station_map.png"
+    );
+
+    fn station(latitude: f64, longitude: f64, free_bikes: Option<u32>) -> Station {
+        Station {
+            free_bikes,
+            empty_slots: Some(5),
+            id: format!("{},{}", latitude, longitude),
+            latitude,
+            longitude,
+            name: String::new(),
+            timestamp: Utc::now(),
+            extra: None,
+            network_href: None,
+            timezone: None,
+            prediction: None,
+        }
+    }
+
+    fn pixels(png_data: &[u8]) -> (u32, u32, Vec<u8>) {
+        let (info, mut reader) = png::Decoder::new(png_data).read_info().unwrap();
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        (info.width, info.height, pixels)
+    }
+
+    #[test]
+    fn renders_the_snapshot() {
+        let user_location = geoutils::Location::new(38.7223, -9.1393);
+        let stations = vec![
+            station(38.7231, -9.1402, Some(0)),
+            station(38.7215, -9.1380, Some(2)),
+            station(38.7240, -9.1370, Some(12)),
+            station(38.7205, -9.1415, None),
+        ];
+        let rendered = render(&user_location, &stations, 9, StationMode::NeedBike).unwrap();
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(SNAPSHOT, &rendered).unwrap();
+        }
+        let snapshot = std::fs::read(SNAPSHOT).unwrap();
+        assert!(
+            pixels(&rendered) == pixels(&snapshot),
+            "map differs from {}",
+            SNAPSHOT
+        );
+    }
+
+    #[test]
+    fn projects_every_point_inside_the_margins() {
+        let user_location = geoutils::Location::new(38.7223, -9.1393);
+        let stations = vec![
+            station(38.70, -9.20, Some(1)),
+            station(38.75, -9.10, Some(1)),
+            station(38.7223, -9.1393, Some(1)),
+        ];
+        let projection = Projection::new(&user_location, &stations);
+        let (low, high) = (MAP_MARGIN as i32, (f64::from(MAP_SIZE) - MAP_MARGIN) as i32);
+        for station in &stations {
+            let (x, y) = projection.point(station.latitude, station.longitude);
+            assert!(low <= x && x <= high, "x {} out of bounds", x);
+            assert!(low <= y && y <= high, "y {} out of bounds", y);
+        }
+        // The widest span, here the longitude, goes from margin to margin
+        assert_eq!(projection.point(38.7223, -9.20).0, low);
+        assert_eq!(projection.point(38.7223, -9.10).0, high);
+        // North is up
+        assert!(projection.point(38.75, -9.14).1 < projection.point(38.70, -9.14).1);
+    }
+
+    #[test]
+    fn centers_a_lone_user_with_the_minimum_span() {
+        let user_location = geoutils::Location::new(38.7223, -9.1393);
+        let projection = Projection::new(&user_location, &[]);
+        let center = (MAP_SIZE / 2) as i32;
+        assert_eq!(projection.point(38.7223, -9.1393), (center, center));
+        let (_, top) = projection.point(38.7223 + MIN_SPAN / 2.0, -9.1393);
+        assert_eq!(top, MAP_MARGIN as i32);
+    }
+}
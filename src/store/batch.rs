@@ -0,0 +1,151 @@
+/// A single write of a `Batch`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Write {
+    Set {
+        key: String,
+        value: String,
+        expire: Option<usize>,
+    },
+    Del {
+        key: String,
+    },
+    SetAdd {
+        key: String,
+        member: String,
+    },
+    SetRemove {
+        key: String,
+        member: String,
+    },
+    ZAdd {
+        key: String,
+        score: i64,
+        member: String,
+    },
+    ZRem {
+        key: String,
+        member: String,
+    },
+    /// Removes the members with score up to `max`
+    ZRemByScore {
+        key: String,
+        max: i64,
+    },
+}
+
+impl Write {
+    pub fn key(&self) -> &str {
+        match self {
+            Write::Set { key, .. }
+            | Write::Del { key }
+            | Write::SetAdd { key, .. }
+            | Write::SetRemove { key, .. }
+            | Write::ZAdd { key, .. }
+            | Write::ZRem { key, .. }
+            | Write::ZRemByScore { key, .. } => key,
+        }
+    }
+}
+
+/// Checked right before the writes of a `Batch`, in the same transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// `key` holds a value
+    Exists(String),
+    /// `key` holds no value
+    Absent(String),
+    /// The set `key` has fewer than `size` members
+    SetSmallerThan(String, usize),
+}
+
+impl Condition {
+    pub fn key(&self) -> &str {
+        match self {
+            Condition::Exists(key) | Condition::Absent(key) | Condition::SetSmallerThan(key, _) => {
+                key
+            }
+        }
+    }
+}
+
+/// Writes applied all at once by `Store::apply`, as a redis transaction. With
+/// a `condition` they are only applied while it holds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batch {
+    pub condition: Option<Condition>,
+    pub writes: Vec<Write>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Batch::default()
+    }
+
+    pub fn when(condition: Condition) -> Self {
+        Batch {
+            condition: Some(condition),
+            writes: vec![],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Sets `key`, expiring after `expire` seconds if given.
+    pub fn set(&mut self, key: &str, value: &str, expire: Option<usize>) -> &mut Self {
+        self.push(Write::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+            expire,
+        })
+    }
+
+    pub fn del(&mut self, key: &str) -> &mut Self {
+        self.push(Write::Del {
+            key: key.to_string(),
+        })
+    }
+
+    pub fn set_add(&mut self, key: &str, member: &str) -> &mut Self {
+        self.push(Write::SetAdd {
+            key: key.to_string(),
+            member: member.to_string(),
+        })
+    }
+
+    pub fn set_remove(&mut self, key: &str, member: &str) -> &mut Self {
+        self.push(Write::SetRemove {
+            key: key.to_string(),
+            member: member.to_string(),
+        })
+    }
+
+    pub fn zadd(&mut self, key: &str, score: i64, member: &str) -> &mut Self {
+        self.push(Write::ZAdd {
+            key: key.to_string(),
+            score,
+            member: member.to_string(),
+        })
+    }
+
+    pub fn zrem(&mut self, key: &str, member: &str) -> &mut Self {
+        self.push(Write::ZRem {
+            key: key.to_string(),
+            member: member.to_string(),
+        })
+    }
+
+    /// Removes the members of the sorted set `key` with score up to `max`.
+    pub fn zrem_by_score(&mut self, key: &str, max: i64) -> &mut Self {
+        self.push(Write::ZRemByScore {
+            key: key.to_string(),
+            max,
+        })
+    }
+
+    fn push(&mut self, write: Write) -> &mut Self {
+        self.writes.push(write);
+        self
+    }
+}
@@ -0,0 +1,346 @@
+use super::{Batch, Condition, Store, Write};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Process local store, for tests and single instance deploys. Data is lost
+/// on restart. Expired keys are dropped when read.
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+#[derive(Default)]
+struct Data {
+    values: HashMap<String, (String, Option<Instant>)>,
+    sets: HashMap<String, BTreeSet<String>>,
+    sorted_sets: HashMap<String, HashMap<String, i64>>,
+}
+
+impl Data {
+    fn value(&mut self, key: &str) -> Option<String> {
+        let expired = match self.values.get(key) {
+            Some((_, Some(expires_at))) => *expires_at <= Instant::now(),
+            Some((_, None)) => false,
+            None => return None,
+        };
+        if expired {
+            self.values.remove(key);
+            return None;
+        }
+        self.values.get(key).map(|(value, _)| value.clone())
+    }
+
+    fn set(&mut self, key: &str, value: &str, expire: Option<usize>) {
+        let expires_at = expire.map(|seconds| Instant::now() + Duration::from_secs(seconds as u64));
+        self.values
+            .insert(key.to_string(), (value.to_string(), expires_at));
+    }
+
+    fn del(&mut self, key: &str) {
+        self.values.remove(key);
+        self.sets.remove(key);
+        self.sorted_sets.remove(key);
+    }
+
+    fn holds(&mut self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Exists(key) => self.value(key).is_some(),
+            Condition::Absent(key) => self.value(key).is_none(),
+            Condition::SetSmallerThan(key, size) => {
+                self.sets.get(key).map_or(0, |set| set.len()) < *size
+            }
+        }
+    }
+
+    /// Applies `write`, dropping sets and sorted sets left empty as redis.
+    fn write(&mut self, write: &Write) {
+        match write {
+            Write::Set { key, value, expire } => self.set(key, value, *expire),
+            Write::Del { key } => self.del(key),
+            Write::SetAdd { key, member } => {
+                self.sets
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(member.clone());
+            }
+            Write::SetRemove { key, member } => {
+                if let Some(set) = self.sets.get_mut(key) {
+                    set.remove(member);
+                    if set.is_empty() {
+                        self.sets.remove(key);
+                    }
+                }
+            }
+            Write::ZAdd { key, score, member } => {
+                self.sorted_sets
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(member.clone(), *score);
+            }
+            Write::ZRem { key, member } => self.retain_sorted(key, |other, _| other != member),
+            Write::ZRemByScore { key, max } => self.retain_sorted(key, |_, score| score > *max),
+        }
+    }
+
+    fn retain_sorted(&mut self, key: &str, keep: impl Fn(&str, i64) -> bool) {
+        if let Some(sorted_set) = self.sorted_sets.get_mut(key) {
+            sorted_set.retain(|member, score| keep(member, *score));
+            if sorted_set.is_empty() {
+                self.sorted_sets.remove(key);
+            }
+        }
+    }
+}
+
+impl MemoryStore {
+    fn data(&self) -> MutexGuard<'_, Data> {
+        self.data.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn keys(&self, pattern: Option<&str>) -> Result<Vec<String>> {
+        let pattern = pattern.unwrap_or("*");
+        let mut data = self.data();
+        let now = Instant::now();
+        data.values.retain(
+            |_, (_, expires_at)| !matches!(expires_at, Some(expires_at) if *expires_at <= now),
+        );
+        let keys = data
+            .values
+            .keys()
+            .chain(data.sets.keys())
+            .chain(data.sorted_sets.keys())
+            .filter(|key| glob_match(pattern, key))
+            .cloned()
+            .collect();
+        Ok(keys)
+    }
+
+    async fn get_optional(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.data().value(key))
+    }
+
+    async fn get_multiple(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let mut data = self.data();
+        Ok(keys.iter().map(|key| data.value(key)).collect())
+    }
+
+    async fn set_multiple(&self, tuples: &[(String, String)], expire: Option<usize>) -> Result<()> {
+        let mut data = self.data();
+        tuples
+            .iter()
+            .for_each(|(key, value)| data.set(key, value, expire));
+        Ok(())
+    }
+
+    async fn del_multiple(&self, keys: &[String]) -> Result<()> {
+        let mut data = self.data();
+        keys.iter().for_each(|key| data.del(key));
+        Ok(())
+    }
+
+    async fn set_add(&self, key: &str, members: &[String]) -> Result<()> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        let mut data = self.data();
+        let set = data.sets.entry(key.to_string()).or_default();
+        set.extend(members.iter().cloned());
+        Ok(())
+    }
+
+    async fn set_remove(&self, key: &str, members: &[String]) -> Result<()> {
+        let mut data = self.data();
+        if let Some(set) = data.sets.get_mut(key) {
+            members.iter().for_each(|member| {
+                set.remove(member);
+            });
+            if set.is_empty() {
+                data.sets.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_members(&self, key: &str) -> Result<Vec<String>> {
+        let data = self.data();
+        let members = data
+            .sets
+            .get(key)
+            .map(|set| set.iter().cloned().collect())
+            .unwrap_or_default();
+        Ok(members)
+    }
+
+    async fn zadd_multiple(&self, key: &str, members: &[(i64, String)]) -> Result<()> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        let mut data = self.data();
+        let sorted_set = data.sorted_sets.entry(key.to_string()).or_default();
+        members.iter().for_each(|(score, member)| {
+            sorted_set.insert(member.clone(), *score);
+        });
+        Ok(())
+    }
+
+    async fn zrange_by_score(&self, key: &str, max: i64) -> Result<Vec<String>> {
+        let data = self.data();
+        let mut members: Vec<(i64, String)> = data
+            .sorted_sets
+            .get(key)
+            .map(|sorted_set| {
+                sorted_set
+                    .iter()
+                    .filter(|(_, score)| **score <= max)
+                    .map(|(member, score)| (*score, member.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        members.sort();
+        Ok(members.into_iter().map(|(_, member)| member).collect())
+    }
+
+    async fn zrem_by_score(&self, key: &str, max: i64) -> Result<()> {
+        let mut data = self.data();
+        if let Some(sorted_set) = data.sorted_sets.get_mut(key) {
+            sorted_set.retain(|_, score| *score > max);
+        }
+        Ok(())
+    }
+
+    async fn apply(&self, batch: Batch) -> Result<bool> {
+        let mut data = self.data();
+        if let Some(condition) = &batch.condition {
+            if !data.holds(condition) {
+                return Ok(false);
+            }
+        }
+        batch.writes.iter().for_each(|write| data.write(write));
+        Ok(true)
+    }
+}
+
+/// Redis `KEYS` pattern, only the `*` wildcard is supported.
+fn glob_match(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    if !key.starts_with(first) {
+        return false;
+    }
+    let mut rest = &key[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(index) => rest = &rest[index + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn applies_batches_at_once() {
+        let store = MemoryStore::default();
+        let mut batch = Batch::new();
+        batch
+            .set("warn", "1", None)
+            .zadd("schedule", 10, "warn")
+            .zadd("expiry", 20, "warn")
+            .set_add("chat", "warn");
+        assert!(store.apply(batch).await.unwrap());
+        assert_eq!(store.get("warn").await.unwrap(), "1");
+        assert_eq!(store.set_members("chat").await.unwrap(), vec!["warn"]);
+        assert_eq!(
+            store.zrange_by_score("expiry", 20).await.unwrap(),
+            vec!["warn"]
+        );
+
+        let mut batch = Batch::new();
+        batch
+            .del("warn")
+            .zrem("schedule", "warn")
+            .zrem_by_score("expiry", 20)
+            .set_remove("chat", "warn");
+        assert!(store.apply(batch).await.unwrap());
+        assert!(store.keys(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn applies_batches_only_while_the_condition_holds() {
+        let store = MemoryStore::default();
+        let update = |value: &str| {
+            let mut batch = Batch::when(Condition::Exists("warn".to_string()));
+            batch.set("warn", value, None).zadd("schedule", 30, "warn");
+            batch
+        };
+        assert!(!store.apply(update("1")).await.unwrap());
+        assert_eq!(store.get_optional("warn").await.unwrap(), None);
+        assert!(store
+            .zrange_by_score("schedule", i64::MAX)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut create = Batch::when(Condition::Absent("warn".to_string()));
+        create.set("warn", "1", None);
+        assert!(store.apply(create.clone()).await.unwrap());
+        assert!(!store.apply(create).await.unwrap());
+        assert!(store.apply(update("2")).await.unwrap());
+        assert_eq!(store.get("warn").await.unwrap(), "2");
+    }
+
+    #[tokio::test]
+    async fn limits_set_sizes() {
+        let store = MemoryStore::default();
+        let add = |member: &str| {
+            let mut batch = Batch::when(Condition::SetSmallerThan("set".to_string(), 2));
+            batch.set_add("set", member);
+            batch
+        };
+        assert!(store.apply(add("a")).await.unwrap());
+        assert!(store.apply(add("b")).await.unwrap());
+        assert!(!store.apply(add("c")).await.unwrap());
+        assert_eq!(store.set_members("set").await.unwrap(), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn drops_expired_keys() {
+        let store = MemoryStore::default();
+        store
+            .set_multiple(&[("key".to_string(), "value".to_string())], Some(0))
+            .await
+            .unwrap();
+        assert_eq!(store.get_optional("key").await.unwrap(), None);
+        assert!(store.keys(None).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn matches_keys_pattern() {
+        assert!(glob_match("*", "ACTIVE_STATIONS_WARN:1"));
+        assert!(glob_match(
+            "ACTIVE_STATIONS_WARN:*",
+            "ACTIVE_STATIONS_WARN:1"
+        ));
+        assert!(glob_match("*:WARN:*", "CHAT:WARN:1"));
+        assert!(!glob_match(
+            "ACTIVE_STATIONS_WARN:*",
+            "CHAT_STATION_WARNS:1"
+        ));
+        assert!(!glob_match("LANGUAGE", "LANGUAGE:1"));
+    }
+}
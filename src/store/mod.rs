@@ -0,0 +1,72 @@
+mod batch;
+mod memory;
+mod sqlite;
+use crate::config::Config;
+use crate::redis_helper::RedisStore;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+pub use batch::{Batch, Condition, Write};
+pub use memory::MemoryStore;
+use sqlite::SqliteStore;
+use std::sync::Arc;
+const MEMORY_SCHEME: &str = "memory";
+const SQLITE_SCHEME: &str = "sqlite";
+
+/// Handle to the configured store, created once at startup.
+pub type Storage = Arc<dyn Store>;
+
+/// Key value persistence used by the bot, with redis like sets and sorted
+/// sets for the chat indexes and the warn schedule. Writes that go together
+/// are grouped in a `Batch`.
+#[async_trait]
+pub trait Store: Send + Sync {
+    async fn keys(&self, pattern: Option<&str>) -> Result<Vec<String>>;
+    async fn get_optional(&self, key: &str) -> Result<Option<String>>;
+    async fn get_multiple(&self, keys: &[String]) -> Result<Vec<Option<String>>>;
+    /// Sets every `(key, value)`, expiring after `expire` seconds if given.
+    async fn set_multiple(&self, tuples: &[(String, String)], expire: Option<usize>) -> Result<()>;
+    async fn del_multiple(&self, keys: &[String]) -> Result<()>;
+
+    async fn set_add(&self, key: &str, members: &[String]) -> Result<()>;
+    async fn set_remove(&self, key: &str, members: &[String]) -> Result<()>;
+    async fn set_members(&self, key: &str) -> Result<Vec<String>>;
+
+    async fn zadd_multiple(&self, key: &str, members: &[(i64, String)]) -> Result<()>;
+    /// Members of the sorted set `key` with score up to `max`.
+    async fn zrange_by_score(&self, key: &str, max: i64) -> Result<Vec<String>>;
+    /// Removes members of the sorted set `key` with score up to `max`.
+    async fn zrem_by_score(&self, key: &str, max: i64) -> Result<()>;
+    /// Applies every write of `batch` at once, if its condition holds.
+    /// Returns whether it was applied.
+    async fn apply(&self, batch: Batch) -> Result<bool>;
+
+    async fn get(&self, key: &str) -> Result<String> {
+        self.get_optional(key)
+            .await?
+            .ok_or_else(|| anyhow!("Missing key `{}`", key))
+    }
+}
+
+/// Picks the store from the `DATABASE_URL` scheme, `memory://` keeps
+/// everything in process, `sqlite://path/to/file.db` uses a local database
+/// file and anything else is handed to redis.
+pub fn connect(config: &Config) -> Result<Storage> {
+    let scheme = config.database_url.split(':').next().unwrap_or_default();
+    let storage: Storage = match scheme {
+        MEMORY_SCHEME => Arc::new(MemoryStore::default()),
+        SQLITE_SCHEME => {
+            let path = config
+                .database_url
+                .strip_prefix("sqlite:")
+                .map(|path| path.trim_start_matches("//"))
+                .filter(|path| !path.is_empty())
+                .ok_or_else(|| anyhow!("Missing sqlite file in `{}`", config.database_url))?;
+            Arc::new(SqliteStore::open(path)?)
+        }
+        _ => Arc::new(RedisStore::new(
+            &config.database_url,
+            config.redis_pool_size,
+        )?),
+    };
+    Ok(storage)
+}
@@ -0,0 +1,486 @@
+use super::{Batch, Condition, Store, Write};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::sync::{Arc, Mutex};
+use tokio::task;
+
+/// Applied in order, `PRAGMA user_version` records how many already ran.
+const MIGRATIONS: &[&str] = &[
+    // 1: values, sets and sorted sets mirroring the redis keys
+    "CREATE TABLE entries (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        expires_at INTEGER
+    );
+    CREATE INDEX entries_expires_at ON entries (expires_at);
+    CREATE TABLE set_members (
+        key TEXT NOT NULL,
+        member TEXT NOT NULL,
+        PRIMARY KEY (key, member)
+    );
+    CREATE TABLE sorted_set_members (
+        key TEXT NOT NULL,
+        member TEXT NOT NULL,
+        score INTEGER NOT NULL,
+        PRIMARY KEY (key, member)
+    );
+    CREATE INDEX sorted_set_members_score ON sorted_set_members (key, score);",
+];
+
+/// File based store for deploys without redis. Queries run on the blocking
+/// thread pool, one at a time over a single connection.
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<SqliteStore> {
+        let mut connection = Connection::open(path)?;
+        migrate(&mut connection)?;
+        Ok(SqliteStore {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn run<T, F>(&self, query: F) -> Result<T>
+    where
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let result = task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|err| err.into_inner());
+            query(&mut connection)
+        })
+        .await?;
+        Ok(result?)
+    }
+}
+
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let version: i64 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        log::info!("Running sqlite migration {}", index + 1);
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", &(index as i64 + 1))?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+fn set_value(
+    transaction: &Transaction,
+    key: &str,
+    value: &str,
+    expire: Option<usize>,
+) -> rusqlite::Result<()> {
+    let expires_at = expire.map(|seconds| Utc::now().timestamp() + seconds as i64);
+    transaction.execute(
+        "INSERT OR REPLACE INTO entries (key, value, expires_at) VALUES (?1, ?2, ?3)",
+        params![key, value, expires_at],
+    )?;
+    Ok(())
+}
+
+fn delete_key(transaction: &Transaction, key: &str) -> rusqlite::Result<()> {
+    transaction.execute("DELETE FROM entries WHERE key = ?1", params![key])?;
+    transaction.execute("DELETE FROM set_members WHERE key = ?1", params![key])?;
+    transaction.execute(
+        "DELETE FROM sorted_set_members WHERE key = ?1",
+        params![key],
+    )?;
+    Ok(())
+}
+
+fn get_value(connection: &Connection, key: &str) -> rusqlite::Result<Option<String>> {
+    connection
+        .query_row(
+            "SELECT value FROM entries
+            WHERE key = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+            params![key, Utc::now().timestamp()],
+            |row| row.get(0),
+        )
+        .optional()
+}
+
+fn holds(connection: &Connection, condition: &Condition) -> rusqlite::Result<bool> {
+    match condition {
+        Condition::Exists(key) => Ok(get_value(connection, key)?.is_some()),
+        Condition::Absent(key) => Ok(get_value(connection, key)?.is_none()),
+        Condition::SetSmallerThan(key, size) => {
+            let count: i64 = connection.query_row(
+                "SELECT COUNT(*) FROM set_members WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )?;
+            Ok(count < *size as i64)
+        }
+    }
+}
+
+fn apply_write(transaction: &Transaction, write: &Write) -> rusqlite::Result<()> {
+    match write {
+        Write::Set { key, value, expire } => set_value(transaction, key, value, *expire)?,
+        Write::Del { key } => delete_key(transaction, key)?,
+        Write::SetAdd { key, member } => {
+            transaction.execute(
+                "INSERT OR IGNORE INTO set_members (key, member) VALUES (?1, ?2)",
+                params![key, member],
+            )?;
+        }
+        Write::SetRemove { key, member } => {
+            transaction.execute(
+                "DELETE FROM set_members WHERE key = ?1 AND member = ?2",
+                params![key, member],
+            )?;
+        }
+        Write::ZAdd { key, score, member } => {
+            transaction.execute(
+                "INSERT OR REPLACE INTO sorted_set_members (key, member, score)
+                VALUES (?1, ?2, ?3)",
+                params![key, member, score],
+            )?;
+        }
+        Write::ZRem { key, member } => {
+            transaction.execute(
+                "DELETE FROM sorted_set_members WHERE key = ?1 AND member = ?2",
+                params![key, member],
+            )?;
+        }
+        Write::ZRemByScore { key, max } => {
+            transaction.execute(
+                "DELETE FROM sorted_set_members WHERE key = ?1 AND score <= ?2",
+                params![key, max],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn keys(&self, pattern: Option<&str>) -> Result<Vec<String>> {
+        // GLOB shares the `*` wildcard with redis KEYS
+        let pattern = pattern.unwrap_or("*").to_string();
+        self.run(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT key FROM entries
+                WHERE key GLOB ?1 AND (expires_at IS NULL OR expires_at > ?2)
+                UNION SELECT key FROM set_members WHERE key GLOB ?1
+                UNION SELECT key FROM sorted_set_members WHERE key GLOB ?1",
+            )?;
+            let keys = statement
+                .query_map(params![pattern, Utc::now().timestamp()], |row| row.get(0))?
+                .collect();
+            keys
+        })
+        .await
+    }
+
+    async fn get_optional(&self, key: &str) -> Result<Option<String>> {
+        let key = key.to_string();
+        self.run(move |connection| get_value(connection, &key))
+            .await
+    }
+
+    async fn get_multiple(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let keys = keys.to_vec();
+        self.run(move |connection| keys.iter().map(|key| get_value(connection, key)).collect())
+            .await
+    }
+
+    async fn set_multiple(&self, tuples: &[(String, String)], expire: Option<usize>) -> Result<()> {
+        let tuples = tuples.to_vec();
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            // Expired entries are only filtered on read, drop them here
+            transaction.execute(
+                "DELETE FROM entries WHERE expires_at <= ?1",
+                params![Utc::now().timestamp()],
+            )?;
+            for (key, value) in &tuples {
+                set_value(&transaction, key, value, expire)?;
+            }
+            transaction.commit()
+        })
+        .await
+    }
+
+    async fn del_multiple(&self, keys: &[String]) -> Result<()> {
+        let keys = keys.to_vec();
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            for key in &keys {
+                delete_key(&transaction, key)?;
+            }
+            transaction.commit()
+        })
+        .await
+    }
+
+    async fn set_add(&self, key: &str, members: &[String]) -> Result<()> {
+        let key = key.to_string();
+        let members = members.to_vec();
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            for member in &members {
+                transaction.execute(
+                    "INSERT OR IGNORE INTO set_members (key, member) VALUES (?1, ?2)",
+                    params![key, member],
+                )?;
+            }
+            transaction.commit()
+        })
+        .await
+    }
+
+    async fn set_remove(&self, key: &str, members: &[String]) -> Result<()> {
+        let key = key.to_string();
+        let members = members.to_vec();
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            for member in &members {
+                transaction.execute(
+                    "DELETE FROM set_members WHERE key = ?1 AND member = ?2",
+                    params![key, member],
+                )?;
+            }
+            transaction.commit()
+        })
+        .await
+    }
+
+    async fn set_members(&self, key: &str) -> Result<Vec<String>> {
+        let key = key.to_string();
+        self.run(move |connection| {
+            let mut statement =
+                connection.prepare("SELECT member FROM set_members WHERE key = ?1")?;
+            let members = statement
+                .query_map(params![key], |row| row.get(0))?
+                .collect();
+            members
+        })
+        .await
+    }
+
+    async fn zadd_multiple(&self, key: &str, members: &[(i64, String)]) -> Result<()> {
+        let key = key.to_string();
+        let members = members.to_vec();
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            for (score, member) in &members {
+                transaction.execute(
+                    "INSERT OR REPLACE INTO sorted_set_members (key, member, score)
+                    VALUES (?1, ?2, ?3)",
+                    params![key, member, score],
+                )?;
+            }
+            transaction.commit()
+        })
+        .await
+    }
+
+    async fn zrange_by_score(&self, key: &str, max: i64) -> Result<Vec<String>> {
+        let key = key.to_string();
+        self.run(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT member FROM sorted_set_members
+                WHERE key = ?1 AND score <= ?2 ORDER BY score, member",
+            )?;
+            let members = statement
+                .query_map(params![key, max], |row| row.get(0))?
+                .collect();
+            members
+        })
+        .await
+    }
+
+    async fn zrem_by_score(&self, key: &str, max: i64) -> Result<()> {
+        let key = key.to_string();
+        self.run(move |connection| {
+            connection.execute(
+                "DELETE FROM sorted_set_members WHERE key = ?1 AND score <= ?2",
+                params![key, max],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn apply(&self, batch: Batch) -> Result<bool> {
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            if let Some(condition) = &batch.condition {
+                if !holds(&transaction, condition)? {
+                    return Ok(false);
+                }
+            }
+            for write in &batch.writes {
+                apply_write(&transaction, write)?;
+            }
+            transaction.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> SqliteStore {
+        SqliteStore::open(":memory:").unwrap()
+    }
+
+    #[tokio::test]
+    async fn gets_sets_and_expires_values() {
+        let store = store();
+        let tuples = [
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "2".to_string()),
+        ];
+        store.set_multiple(&tuples, None).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), "1");
+        assert_eq!(
+            store
+                .get_multiple(&["b".to_string(), "c".to_string()])
+                .await
+                .unwrap(),
+            vec![Some("2".to_string()), None]
+        );
+
+        store
+            .set_multiple(&[("a".to_string(), "3".to_string())], Some(0))
+            .await
+            .unwrap();
+        assert_eq!(store.get_optional("a").await.unwrap(), None);
+        assert_eq!(store.keys(None).await.unwrap(), vec!["b"]);
+
+        store.del_multiple(&["b".to_string()]).await.unwrap();
+        assert!(store.keys(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_sets_and_sorted_sets() {
+        let store = store();
+        let members = ["a".to_string(), "b".to_string()];
+        store.set_add("set", &members).await.unwrap();
+        store.set_remove("set", &members[..1]).await.unwrap();
+        assert_eq!(store.set_members("set").await.unwrap(), vec!["b"]);
+
+        let scored = [
+            (20, "b".to_string()),
+            (10, "a".to_string()),
+            (30, "c".to_string()),
+        ];
+        store.zadd_multiple("zset", &scored).await.unwrap();
+        assert_eq!(
+            store.zrange_by_score("zset", 20).await.unwrap(),
+            vec!["a", "b"]
+        );
+        store.zrem_by_score("zset", 10).await.unwrap();
+        assert_eq!(
+            store.zrange_by_score("zset", i64::MAX).await.unwrap(),
+            vec!["b", "c"]
+        );
+        assert_eq!(store.keys(Some("*set")).await.unwrap().len(), 2);
+
+        store
+            .del_multiple(&["set".to_string(), "zset".to_string()])
+            .await
+            .unwrap();
+        assert!(store.keys(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn applies_batches_at_once() {
+        let store = store();
+        let mut batch = Batch::new();
+        batch
+            .set("warn", "1", Some(60))
+            .zadd("schedule", 10, "warn")
+            .zadd("expiry", 20, "warn")
+            .set_add("chat", "warn");
+        assert!(store.apply(batch).await.unwrap());
+        assert_eq!(store.get("warn").await.unwrap(), "1");
+        assert_eq!(store.set_members("chat").await.unwrap(), vec!["warn"]);
+        assert_eq!(
+            store.zrange_by_score("schedule", 10).await.unwrap(),
+            vec!["warn"]
+        );
+
+        let mut batch = Batch::new();
+        batch
+            .del("warn")
+            .zrem("schedule", "warn")
+            .zrem_by_score("expiry", 20)
+            .set_remove("chat", "warn");
+        assert!(store.apply(batch).await.unwrap());
+        assert!(store.keys(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn applies_batches_only_while_the_condition_holds() {
+        let store = store();
+        let update = |value: &str| {
+            let mut batch = Batch::when(Condition::Exists("warn".to_string()));
+            batch.set("warn", value, None).zadd("schedule", 30, "warn");
+            batch
+        };
+        assert!(!store.apply(update("1")).await.unwrap());
+        assert!(store.keys(None).await.unwrap().is_empty());
+
+        let mut create = Batch::when(Condition::Absent("warn".to_string()));
+        create.set("warn", "1", None);
+        assert!(store.apply(create.clone()).await.unwrap());
+        assert!(!store.apply(create).await.unwrap());
+        assert!(store.apply(update("2")).await.unwrap());
+        assert_eq!(store.get("warn").await.unwrap(), "2");
+
+        let add = |member: &str| {
+            let mut batch = Batch::when(Condition::SetSmallerThan("set".to_string(), 2));
+            batch.set_add("set", member);
+            batch
+        };
+        assert!(store.apply(add("a")).await.unwrap());
+        assert!(store.apply(add("b")).await.unwrap());
+        assert!(!store.apply(add("c")).await.unwrap());
+        assert_eq!(store.set_members("set").await.unwrap(), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn migrates_from_older_versions() {
+        let path = std::env::temp_dir().join(format!("ya_bike_bot-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        let user_version = |path: &str| -> i64 {
+            Connection::open(path)
+                .unwrap()
+                .pragma_query_value(None, "user_version", |row| row.get(0))
+                .unwrap()
+        };
+        // A database created before any migration ran
+        Connection::open(path)
+            .unwrap()
+            .execute_batch("CREATE TABLE unrelated (id INTEGER);")
+            .unwrap();
+        assert_eq!(user_version(path), 0);
+
+        let store = SqliteStore::open(path).unwrap();
+        assert_eq!(user_version(path), MIGRATIONS.len() as i64);
+        store
+            .set_multiple(&[("key".to_string(), "value".to_string())], None)
+            .await
+            .unwrap();
+        drop(store);
+
+        // Applied migrations aren't run again
+        let store = SqliteStore::open(path).unwrap();
+        assert_eq!(store.get("key").await.unwrap(), "value");
+        drop(store);
+        std::fs::remove_file(path).unwrap();
+    }
+}
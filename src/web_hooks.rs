@@ -10,7 +10,7 @@ async fn handle_rejection(error: warp::Rejection) -> Result<impl warp::Reply, In
     Ok(StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-pub async fn webhook<'a>(
+pub async fn webhook(
     bot: Arc<Bot>,
     host: &str,
     port: u16,